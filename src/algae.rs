@@ -1,160 +1,416 @@
+use crate::deadline::Deadline;
 use crate::game::Game;
-use std::sync::mpsc;
-use std::thread;
-use std::time::Instant;
+use crate::ordering::MoveOrdering;
+use crate::pool::ThreadPool;
+use crate::transposition::{Bound, Entry, TranspositionTable};
+use serde::Deserialize;
+use std::collections::{BinaryHeap, VecDeque};
+use std::str::FromStr;
+use std::sync::Arc;
 
-pub fn best_move(game: &mut Game, depth: u8, search_time: i128) -> Option<(u8, f64)> {
-    let start = Instant::now();
-    if search_time < 0 {
-        return None;
+// Scores a position from snake 0's point of view, higher being better for it. Positions where our
+// squad is already dead are scored by the search itself, so they never get here.
+pub trait Evaluator: Send + Sync + 'static {
+    fn eval(&self, game: &Game) -> f64;
+
+    // Scores the position from every snake's point of view at once, for searches where each snake
+    // plays for itself. Dead snakes get scored too.
+    fn eval_all(&self, game: &Game) -> Vec<f64> {
+        (0..game.snakes.len())
+            .map(|snake_idx| self.eval(&game.perspective(snake_idx)))
+            .collect()
     }
-    let mut best_move = (0, f64::NEG_INFINITY);
 
-    let mut moves = Vec::new();
-    for direction in 0..4 {
-        if game.snakes[0].positions.len() == 1 && direction == 2 {
-            continue;
+    // The score of a won position, and minus the score of a lost one. Scores this big are final,
+    // so iterative deepening stops once it finds one.
+    fn win(&self) -> f64 {
+        10000.0
+    }
+}
+
+// The evaluation the bot plays with unless it's given another one.
+#[derive(Clone, Debug, Default)]
+pub struct DefaultEvaluator {
+    pub weights: EvalWeights,
+}
+
+impl Evaluator for DefaultEvaluator {
+    fn eval(&self, game: &Game) -> f64 {
+        eval(game, &self.weights)
+    }
+
+    fn eval_all(&self, game: &Game) -> Vec<f64> {
+        eval_all(game, &self.weights)
+    }
+
+    fn win(&self) -> f64 {
+        self.weights.win
+    }
+}
+
+// The knobs of the default evaluation, so deployments can play with different weights and the
+// tournament can compare them without recompiling.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct EvalWeights {
+    pub win: f64,
+    // Per square of body, including growth still to come.
+    pub length: f64,
+    // For getting to every square of the board before anyone else does.
+    pub territory: f64,
+    // Per apple we get to before anyone else does.
+    pub food: f64,
+    // Per square to the nearest apple we get to first, scaled by how hungry we are.
+    pub hunger: f64,
+    // How much of a square's territory value is lost when it's in hazard sauce.
+    pub hazard_territory: f64,
+    // Per point of health it would cost to get out of the sauce, when our head is in it.
+    pub hazard_exit: f64,
+    // Health above this is good, below it is bad...
+    pub health_offset: f64,
+    // ...by one point per this much health.
+    pub health_divisor: f64,
+    // For being able to reach every square of the board, counting squares bodies will have moved
+    // out of by the time we get there.
+    pub area: f64,
+    // Penalty for a snake shut in a pocket smaller than itself.
+    pub trapped: f64,
+}
+
+impl Default for EvalWeights {
+    fn default() -> EvalWeights {
+        EvalWeights {
+            win: 10000.0,
+            length: 1.0,
+            territory: 10.0,
+            food: 1.0,
+            hunger: 1.0,
+            hazard_territory: 0.5,
+            hazard_exit: 0.2,
+            health_offset: 50.0,
+            health_divisor: 5.0,
+            area: 10.0,
+            trapped: 1000.0,
         }
-        let new_head_signed = match direction {
-            0 => game.snakes[0].positions[0] as i16 + game.width as i16,
-            1 => game.snakes[0].positions[0] as i16 + 1,
-            2 => game.snakes[0].positions[0] as i16 - game.width as i16,
-            3 => game.snakes[0].positions[0] as i16 - 1,
-            _ => panic!("Invalid direction"),
+    }
+}
+
+impl EvalWeights {
+    // Reads weights from either a JSON object or the path of a file holding one. Weights that
+    // aren't given keep their defaults.
+    pub fn load(source: &str) -> EvalWeights {
+        let json = if source.trim_start().starts_with('{') {
+            source.to_string()
+        } else {
+            std::fs::read_to_string(source)
+                .unwrap_or_else(|err| panic!("Couldn't read weights from {}: {}", source, err))
         };
-        if new_head_signed < 0
-            || new_head_signed >= game.width as i16 * game.height as i16
-            || (new_head_signed % game.width as i16 == 0 && direction == 1)
-            || ((new_head_signed + 1) % game.width as i16 == 0 && direction == 3)
-        {
-            continue;
+        serde_json::from_str(&json).expect("Weights are not valid JSON")
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SearchMode {
+    // Paranoid for two squads, max-n for more.
+    Auto,
+    // Every opponent plays to hurt us, as if they were all on one team.
+    Paranoid,
+    // Every snake plays for its own score.
+    MaxN,
+    // Paranoid, but only one opponent gets to pick its move each turn while the rest play
+    // default_move, so crowded boards don't blow up the number of replies to search.
+    BestReply,
+}
+
+impl SearchMode {
+    // Picks the search for a position, which is the mode itself unless it's Auto.
+    pub fn resolve(self, game: &Game) -> SearchMode {
+        if self != SearchMode::Auto {
+            return self;
         }
-        if game.snakes[0].positions.len() > 1
-            && (new_head_signed
-                != game.snakes[0].positions[game.snakes[0].positions.len() - 1] as i16
-                && game.snakes[0].snake_arr[new_head_signed as usize])
-        {
-            continue;
+        let opponent_squads = (1..game.snakes.len())
+            .filter(|idx| !game.same_squad(0, *idx) && !squad_dead(game, *idx))
+            .filter(|idx| (1..*idx).all(|other| !game.same_squad(*idx, other)))
+            .count();
+        if opponent_squads >= 2 {
+            SearchMode::MaxN
+        } else {
+            SearchMode::Paranoid
+        }
+    }
+}
+
+impl FromStr for SearchMode {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<SearchMode, String> {
+        match name {
+            "auto" => Ok(SearchMode::Auto),
+            "paranoid" => Ok(SearchMode::Paranoid),
+            "max-n" => Ok(SearchMode::MaxN),
+            "best-reply" => Ok(SearchMode::BestReply),
+            _ => Err(format!("Unknown search mode {}", name)),
         }
-        moves.push(direction);
     }
-    let num_moves = moves.len();
+}
 
-    let (tx, rx) = mpsc::channel();
-    for direction in moves {
+// Tables shared between the search threads and kept from one iterative deepening depth to the
+// next, along with the evaluator the search scores positions with and the kind of search to run,
+// which should already be resolved for the position.
+pub struct SearchContext<E: Evaluator> {
+    pub table: TranspositionTable,
+    pub ordering: MoveOrdering,
+    pub evaluator: E,
+    pub mode: SearchMode,
+}
+
+impl<E: Evaluator> SearchContext<E> {
+    pub fn new(table_size: usize, squares: usize, evaluator: E) -> SearchContext<E> {
+        SearchContext {
+            table: TranspositionTable::new(table_size),
+            ordering: MoveOrdering::new(squares),
+            evaluator,
+            mode: SearchMode::Paranoid,
+        }
+    }
+}
+
+// Searches one depth deeper at a time until the deadline passes or max_depth is done, returning the
+// best move and score from the deepest search that finished (or finished enough to trust), along
+// with that depth.
+pub fn iterative_deepening<E: Evaluator>(
+    game: &mut Game,
+    max_depth: u8,
+    pool: &ThreadPool,
+    deadline: &Arc<Deadline>,
+    context: &Arc<SearchContext<E>>,
+) -> (u8, f64, u8) {
+    // if not even depth 1 finishes, at least don't run into a wall
+    let mut best = (default_move(game, 0), 0.0, 0);
+    for depth in 1..=max_depth {
+        let prev_best = if depth > 1 { Some(best.0) } else { None };
+        match best_move(game, depth, prev_best, pool, deadline, context) {
+            Some((direction, score)) => best = (direction, score, depth),
+            None => break,
+        }
+        // a forced win or loss won't change with more depth
+        if best.1.abs() >= context.evaluator.win() {
+            break;
+        }
+    }
+    best
+}
+
+// Searches the root to the given depth. The calling thread does the search whose result is used,
+// so every game makes progress however busy the pool is, and idle workers run helper searches of
+// the same position with the root moves in a different order and sometimes a ply deeper, which
+// fill in the shared tables for it (Lazy SMP).
+// If the deadline cuts the search short, the moves that did finish are still used, as long as one
+// of them is prev_best, the best move from the previous depth: anything that beats it at the new
+// depth beats it for real. Without a previous best, whatever finished is better than nothing.
+pub fn best_move<E: Evaluator>(
+    game: &mut Game,
+    depth: u8,
+    prev_best: Option<u8>,
+    pool: &ThreadPool,
+    deadline: &Arc<Deadline>,
+    context: &Arc<SearchContext<E>>,
+) -> Option<(u8, f64)> {
+    if deadline.expired() {
+        return None;
+    }
+    let mut moves = search_moves(game, 0);
+    // the previous best goes first, so it's finished if anything is
+    let head = game.snakes[0].positions[0];
+    context.ordering.order(&mut moves, head, 0, 0, prev_best);
+
+    // max-n doesn't use the shared tables, so helpers would have nothing to pass on. Otherwise the
+    // calling thread and the helpers together use as many threads as the pool has, and workers
+    // busy with another game's search are left to it.
+    let helpers = if context.mode == SearchMode::MaxN {
+        0
+    } else {
+        (pool.size() - 1).min(pool.idle())
+    };
+    let helper_deadline = Arc::new(deadline.child());
+    for helper in 1..=helpers {
         let mut game = game.clone();
-        let tx = tx.clone();
-        thread::spawn(move || {
-            // maybe switch to futures if it's not much slower
-            let _ = tx.send(
-                min_rec(
-                    &mut game,
-                    &mut vec![direction],
-                    best_move.1,
-                    f64::INFINITY,
-                    depth,
-                    search_time - start.elapsed().as_millis() as i128,
-                )
-                .map(|x| (direction, x)),
-            );
+        let mut moves = moves.clone();
+        let shift = helper % moves.len();
+        moves.rotate_left(shift);
+        let helper_depth = depth.saturating_add((helper % 2) as u8);
+        let deadline = helper_deadline.clone();
+        let context = context.clone();
+        pool.execute(move || {
+            root_search(&mut game, helper_depth, &moves, &deadline, &context);
         });
     }
-    for _ in 0..num_moves {
-        let potential_best_move = rx.recv().expect("Failed to read from thread receiver.");
-        match potential_best_move {
-            Some(potential_best_move) => {
-                if potential_best_move.1 > best_move.1 {
-                    best_move = potential_best_move;
-                }
+    // a search cut off by the deadline doesn't unmove on its way out, so it gets its own copy
+    let result = root_search(&mut game.clone(), depth, &moves, deadline, context);
+    helper_deadline.stop();
+    result
+}
+
+// Searches the root moves in order, narrowing the window as it goes. Stops at the deadline, handing
+// back the best of the moves that finished if there were any.
+fn root_search<E: Evaluator>(
+    game: &mut Game,
+    depth: u8,
+    moves: &[u8],
+    deadline: &Deadline,
+    context: &SearchContext<E>,
+) -> Option<(u8, f64)> {
+    let mut best_move: Option<(u8, f64)> = None;
+    for direction in moves {
+        let alpha = best_move.map_or(f64::NEG_INFINITY, |best_move| best_move.1);
+        let score = match context.mode {
+            SearchMode::MaxN => {
+                max_n(game, &mut vec![*direction], depth, deadline, context).map(|scores| scores[0])
             }
-            None => return None,
+            _ => min_rec(
+                game,
+                &mut vec![*direction],
+                alpha,
+                f64::INFINITY,
+                depth,
+                0,
+                deadline,
+                context,
+            ),
+        };
+        let score = match score {
+            Some(score) => score,
+            None => break,
+        };
+        if best_move.is_none() || score > alpha {
+            best_move = Some((*direction, score));
         }
     }
-
-    Some(best_move)
+    best_move
 }
 
-pub fn max(
+// depth is unsigned, so <= 0 is the same as == 0
+#[allow(clippy::absurd_extreme_comparisons)]
+pub fn max<E: Evaluator>(
     game: &mut Game,
     mut alpha: f64,
     beta: f64,
     depth: u8,
-    search_time: i128,
+    ply: usize,
+    deadline: &Deadline,
+    context: &SearchContext<E>,
 ) -> Option<f64> {
-    let start = Instant::now();
-    if search_time < 0 {
+    if deadline.expired() {
         return None;
     }
-    if game.snakes[0].health == 0 {
-        return Some(-10000.0);
+    if squad_dead(game, 0) {
+        return Some(-context.evaluator.win());
     }
     if depth <= 0 {
-        return Some(eval(game));
+        return Some(context.evaluator.eval(game));
     }
-    for direction in 0..4 {
-        if game.snakes[0].positions.len() == 1 && direction == 2 {
-            continue;
-        }
-        let new_head_signed = match direction {
-            0 => game.snakes[0].positions[0] as i16 + game.width as i16,
-            1 => game.snakes[0].positions[0] as i16 + 1,
-            2 => game.snakes[0].positions[0] as i16 - game.width as i16,
-            3 => game.snakes[0].positions[0] as i16 - 1,
-            _ => panic!("Invalid direction"),
-        };
-        if new_head_signed < 0
-            || new_head_signed >= game.width as i16 * game.height as i16
-            || (new_head_signed % game.width as i16 == 0 && direction == 1)
-            || ((new_head_signed + 1) % game.width as i16 == 0 && direction == 3)
-        {
-            continue;
-        }
-        if game.snakes[0].positions.len() > 1
-            && (new_head_signed
-                != game.snakes[0].positions[game.snakes[0].positions.len() - 1] as i16
-                && game.snakes[0].snake_arr[new_head_signed as usize])
-        {
-            continue;
+    let hash = game.hash;
+    let entry = context.table.get(hash);
+    if let Some(entry) = entry {
+        if entry.depth >= depth {
+            match entry.bound {
+                Bound::Exact => return Some(entry.score.max(alpha).min(beta)),
+                Bound::Lower if entry.score >= beta => return Some(beta),
+                Bound::Upper if entry.score <= alpha => return Some(alpha),
+                _ => {}
+            }
         }
+    }
+    // our snake can be dead while its squad plays on
+    let mut directions = if game.snakes[0].health == 0 {
+        vec![0]
+    } else {
+        search_moves(game, 0)
+    };
+    // the best move from earlier searches of this position is the principal variation's move if
+    // this position is on it, so it goes first
+    let hash_move = entry.and_then(|entry| entry.best_move);
+    let head = game.snakes[0].positions[0];
+    context
+        .ordering
+        .order(&mut directions, head, 0, ply, hash_move);
+    let mut best_move = None;
+    for direction in directions {
         let score = min_rec(
             game,
             &mut vec![direction],
             alpha,
             beta,
             depth,
-            search_time - start.elapsed().as_millis() as i128,
+            ply,
+            deadline,
+            context,
         )?;
         if score >= beta {
+            context
+                .ordering
+                .record_cutoff(head, 0, ply, direction, depth);
+            context.table.insert(
+                hash,
+                Entry {
+                    score: beta,
+                    depth,
+                    bound: Bound::Lower,
+                    best_move: Some(direction),
+                },
+            );
             return Some(beta);
         }
         if score > alpha {
             alpha = score;
+            best_move = Some(direction);
         }
     }
+    context.table.insert(
+        hash,
+        Entry {
+            score: alpha,
+            depth,
+            bound: if best_move.is_some() {
+                Bound::Exact
+            } else {
+                Bound::Upper
+            },
+            best_move,
+        },
+    );
     Some(alpha)
 }
 
-fn min_rec(
+#[allow(clippy::too_many_arguments)]
+fn min_rec<E: Evaluator>(
     game: &mut Game,
     other_snake_moves: &mut Vec<u8>,
-    alpha: f64,
+    mut alpha: f64,
     mut beta: f64,
     depth: u8,
-    search_time: i128,
+    ply: usize,
+    deadline: &Deadline,
+    context: &SearchContext<E>,
 ) -> Option<f64> {
-    let start = Instant::now();
-    if search_time < 0 {
+    if deadline.expired() {
         return None;
     }
-    if other_snake_moves.len() == game.snakes.len() {
-        let prev_state = game.move_snakes(other_snake_moves);
-        let score = max(
+    if context.mode == SearchMode::BestReply && other_snake_moves.len() == 1 {
+        return best_reply(
             game,
+            other_snake_moves[0],
             alpha,
             beta,
-            depth - 1,
-            search_time - start.elapsed().as_millis() as i128,
-        )?;
+            depth,
+            ply,
+            deadline,
+            context,
+        );
+    }
+    if other_snake_moves.len() == game.snakes.len() {
+        let prev_state = game.move_snakes(other_snake_moves);
+        let score = max(game, alpha, beta, depth - 1, ply + 1, deadline, context)?;
         game.unmove_snake(&prev_state);
         if score <= alpha {
             return Some(alpha);
@@ -163,160 +419,582 @@ fn min_rec(
             beta = score;
         }
     } else {
-        if game.snakes[other_snake_moves.len()].health == 0 {
-            other_snake_moves.push(0);
+        let snake_idx = other_snake_moves.len();
+        let head = game.snakes[snake_idx].positions[0];
+        // teammates are ordered right after us, so they pick their moves before any opponent does
+        let teammate = game.same_squad(0, snake_idx);
+        let directions = if game.snakes[snake_idx].health == 0 {
+            vec![0]
+        } else if !teammate && !within_reach(game, snake_idx, depth) {
+            vec![default_move(game, snake_idx)]
+        } else {
+            let mut directions = search_moves(game, snake_idx);
+            context
+                .ordering
+                .order(&mut directions, head, snake_idx, ply, None);
+            directions
+        };
+        for direction in directions {
+            other_snake_moves.push(direction);
             let score = min_rec(
                 game,
                 other_snake_moves,
                 alpha,
                 beta,
                 depth,
-                search_time - start.elapsed().as_millis() as i128,
+                ply,
+                deadline,
+                context,
             )?;
             other_snake_moves.pop();
-            if score <= alpha {
-                return Some(alpha);
+            if teammate {
+                if score >= beta {
+                    context
+                        .ordering
+                        .record_cutoff(head, snake_idx, ply, direction, depth);
+                    return Some(beta);
+                }
+                if score > alpha {
+                    alpha = score;
+                }
+            } else {
+                if score <= alpha {
+                    context
+                        .ordering
+                        .record_cutoff(head, snake_idx, ply, direction, depth);
+                    return Some(alpha);
+                }
+                if score < beta {
+                    beta = score;
+                }
             }
-            if score < beta {
-                beta = score;
+        }
+        if teammate {
+            return Some(alpha);
+        }
+    }
+    Some(beta)
+}
+
+// Max-n: the snakes pick their moves one after another, each taking the one best for its own
+// score, and the scores of every snake for the line they end up on get passed back up. Without a
+// single score to bound there's nothing to cut off, so every move gets searched.
+fn max_n<E: Evaluator>(
+    game: &mut Game,
+    snake_moves: &mut Vec<u8>,
+    depth: u8,
+    deadline: &Deadline,
+    context: &SearchContext<E>,
+) -> Option<Vec<f64>> {
+    if deadline.expired() {
+        return None;
+    }
+    if snake_moves.len() == game.snakes.len() {
+        let prev_state = game.move_snakes(snake_moves);
+        let scores = if depth == 1 || game.is_over() {
+            context.evaluator.eval_all(game)
+        } else {
+            max_n(game, &mut Vec::new(), depth - 1, deadline, context)?
+        };
+        game.unmove_snake(&prev_state);
+        return Some(scores);
+    }
+    let snake_idx = snake_moves.len();
+    let directions = if game.snakes[snake_idx].health == 0 {
+        vec![0]
+    } else if !game.same_squad(0, snake_idx) && !within_reach(game, snake_idx, depth) {
+        vec![default_move(game, snake_idx)]
+    } else {
+        search_moves(game, snake_idx)
+    };
+    let mut best: Option<Vec<f64>> = None;
+    for direction in directions {
+        snake_moves.push(direction);
+        let scores = max_n(game, snake_moves, depth, deadline, context)?;
+        snake_moves.pop();
+        if best
+            .as_ref()
+            .is_none_or(|best| scores[snake_idx] > best[snake_idx])
+        {
+            best = Some(scores);
+        }
+    }
+    best
+}
+
+// Best-Reply Search: instead of trying every combination of opponent moves, each opponent in turn
+// tries each of its moves while everyone else, teammates included, plays default_move, and the
+// worst of those for us is the score. That's a sum of the opponents' moves to search instead of a
+// product.
+#[allow(clippy::too_many_arguments)]
+fn best_reply<E: Evaluator>(
+    game: &mut Game,
+    our_move: u8,
+    alpha: f64,
+    mut beta: f64,
+    depth: u8,
+    ply: usize,
+    deadline: &Deadline,
+    context: &SearchContext<E>,
+) -> Option<f64> {
+    let mut defaults: Vec<u8> = (0..game.snakes.len())
+        .map(|snake_idx| default_move(game, snake_idx))
+        .collect();
+    defaults[0] = our_move;
+    let opponents: Vec<usize> = (1..game.snakes.len())
+        .filter(|idx| {
+            !game.same_squad(0, *idx)
+                && game.snakes[*idx].health > 0
+                && within_reach(game, *idx, depth)
+        })
+        .collect();
+    let mut replies: Vec<Option<(usize, u8)>> = Vec::new();
+    for snake_idx in opponents {
+        let head = game.snakes[snake_idx].positions[0];
+        let mut directions = search_moves(game, snake_idx);
+        context
+            .ordering
+            .order(&mut directions, head, snake_idx, ply, None);
+        replies.extend(directions.into_iter().map(|dir| Some((snake_idx, dir))));
+    }
+    // with nobody left to reply, everyone just plays their default
+    if replies.is_empty() {
+        replies.push(None);
+    }
+    for reply in replies {
+        let mut moves = defaults.clone();
+        if let Some((snake_idx, direction)) = reply {
+            moves[snake_idx] = direction;
+        }
+        let prev_state = game.move_snakes(&moves);
+        let score = max(game, alpha, beta, depth - 1, ply + 1, deadline, context)?;
+        game.unmove_snake(&prev_state);
+        if score <= alpha {
+            if let Some((snake_idx, direction)) = reply {
+                let head = game.snakes[snake_idx].positions[0];
+                context
+                    .ordering
+                    .record_cutoff(head, snake_idx, ply, direction, depth);
             }
-            return Some(beta);
+            return Some(alpha);
+        }
+        if score < beta {
+            beta = score;
+        }
+    }
+    Some(beta)
+}
+
+// A cheap guess at what a snake will do, for snakes the search doesn't branch on: the move onto the
+// square with the most free squares around it, so it doesn't box itself in.
+pub fn default_move(game: &Game, snake_idx: usize) -> u8 {
+    if game.snakes[snake_idx].health == 0 {
+        return 0;
+    }
+    let free = |pos: u16| {
+        game.snakes
+            .iter()
+            .all(|snake| snake.health == 0 || !snake.snake_arr[pos as usize])
+    };
+    let head = game.snakes[snake_idx].positions[0];
+    let mut best = (0, None);
+    for direction in search_moves(game, snake_idx) {
+        let room = match game.move_position(head, direction) {
+            Some(next) => (0..4)
+                .filter_map(|around| game.move_position(next, around))
+                .filter(|pos| free(*pos))
+                .count(),
+            None => 0,
+        };
+        if best.1.is_none() || room > best.0 {
+            best = (room, Some(direction));
+        }
+    }
+    best.1.unwrap_or(0)
+}
+
+// A trapped snake still has to pick a move, so it gets one that kills it instead of none at all.
+pub fn search_moves(game: &Game, snake_idx: usize) -> Vec<u8> {
+    let moves = game.legal_moves(snake_idx);
+    if moves.is_empty() {
+        vec![0]
+    } else {
+        moves
+    }
+}
+
+// Whether the snake's head could get next to one of our squad's snakes within the remaining depth,
+// with our head moving towards it too. Bodies count as well as heads, since a snake pinned against
+// ours has its life decided by what we do. Snakes out of reach can't change how the search goes for
+// us, so they play default_move instead of being branched on.
+fn within_reach(game: &Game, snake_idx: usize, depth: u8) -> bool {
+    let head = game.snakes[snake_idx].positions[0];
+    (0..game.snakes.len())
+        .filter(|idx| game.same_squad(0, *idx) && game.snakes[*idx].health > 0)
+        .flat_map(|idx| game.snakes[idx].positions.iter())
+        .any(|pos| game.distance(head, *pos) <= 2 * depth as u16)
+}
+
+fn squad_dead(game: &Game, snake_idx: usize) -> bool {
+    (0..game.snakes.len())
+        .all(|idx| !game.same_squad(snake_idx, idx) || game.snakes[idx].health == 0)
+}
+
+// Number of turns until each square is free to move onto, assuming no snake eats in the meantime.
+fn vacate_times(game: &Game) -> Vec<u16> {
+    let mut times = vec![0; game.width as usize * game.height as usize];
+    for snake in &game.snakes {
+        if snake.health == 0 {
+            continue;
         }
+        for (pos_idx, pos) in snake.positions.iter().enumerate() {
+            times[*pos as usize] = if game.ruleset.grows_every_turn() {
+                u16::MAX
+            } else {
+                (snake.positions.len() - pos_idx) as u16 + snake.queued as u16
+            };
+        }
+    }
+    times
+}
+
+// Health left after moving onto pos, worked out the same way move_snakes does, with 0 being dead.
+fn health_after(game: &Game, health: u8, pos: u16) -> u8 {
+    let mut health = if game.ruleset.drains_health() {
+        health.saturating_sub(1)
+    } else {
+        health
+    };
+    if game.apples[pos as usize] {
+        health = 100;
+    }
+    let damage = game.hazards[pos as usize] as u16 * game.settings.hazard_damage_per_turn as u16;
+    (health as u16).saturating_sub(damage) as u8
+}
+
+// Counts the squares reachable from start with the given health, letting the search through body
+// squares that will have moved out of the way by the time it gets there. Squares are searched
+// again when a longer way around gets there with more health left, so a detour around the sauce
+// still counts.
+fn flood_fill(game: &Game, start: u16, health: u8, vacate_times: &[u16]) -> u16 {
+    // health on arrival by the best way found so far, 0 for not reached
+    let mut healths = vec![0; vacate_times.len()];
+    healths[start as usize] = health;
+    let mut queue = VecDeque::new();
+    queue.push_back((start, health, 0));
+    let mut area = 0;
+    while let Some((pos, health, dist)) = queue.pop_front() {
         for direction in 0..4 {
-            if game.snakes[other_snake_moves.len()].positions.len() == 1 && direction == 2 {
+            let next = match game.move_position(pos, direction) {
+                Some(next) => next,
+                None => continue,
+            };
+            let next_health = health_after(game, health, next);
+            if next_health <= healths[next as usize] || vacate_times[next as usize] > dist + 1 {
                 continue;
             }
-            let new_head_signed = match direction {
-                0 => game.snakes[other_snake_moves.len()].positions[0] as i16 + game.width as i16,
-                1 => game.snakes[other_snake_moves.len()].positions[0] as i16 + 1,
-                2 => game.snakes[other_snake_moves.len()].positions[0] as i16 - game.width as i16,
-                3 => game.snakes[other_snake_moves.len()].positions[0] as i16 - 1,
-                _ => panic!("Invalid direction"),
+            if healths[next as usize] == 0 {
+                area += 1;
+            }
+            healths[next as usize] = next_health;
+            queue.push_back((next, next_health, dist + 1));
+        }
+    }
+    area
+}
+
+// The least health it costs to walk from start to a square without any sauce, or None if there's
+// no way out.
+fn hazard_exit_cost(game: &Game, start: u16, health: u8, vacate_times: &[u16]) -> Option<u8> {
+    let mut best = vec![0; vacate_times.len()];
+    best[start as usize] = health;
+    // most health left first, which makes this Dijkstra's algorithm over the health spent
+    let mut queue = BinaryHeap::new();
+    queue.push((health, 0, start));
+    while let Some((left, dist, pos)) = queue.pop() {
+        if game.hazards[pos as usize] == 0 {
+            return Some(health.saturating_sub(left));
+        }
+        if left < best[pos as usize] {
+            continue;
+        }
+        for direction in 0..4 {
+            let next = match game.move_position(pos, direction) {
+                Some(next) => next,
+                None => continue,
             };
-            if new_head_signed < 0
-                || new_head_signed >= game.width as i16 * game.height as i16
-                || (new_head_signed % game.width as i16 == 0 && direction == 1)
-                || ((new_head_signed + 1) % game.width as i16 == 0 && direction == 3)
-            {
+            let next_health = health_after(game, left, next);
+            if next_health <= best[next as usize] || vacate_times[next as usize] > dist + 1 {
                 continue;
             }
-            if game.snakes[other_snake_moves.len()].positions.len() > 1
-                && (new_head_signed
-                    != game.snakes[other_snake_moves.len()].positions
-                        [game.snakes[other_snake_moves.len()].positions.len() - 1]
-                        as i16
-                    && game.snakes[other_snake_moves.len()].snake_arr[new_head_signed as usize])
-            {
+            best[next as usize] = next_health;
+            queue.push((next_health, dist + 1, next));
+        }
+    }
+    None
+}
+
+// What a snake gets to before any other snake does.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct Territory {
+    // Not counting the square the head is on.
+    squares: u16,
+    // How many of those squares are in hazard sauce.
+    hazard_squares: u16,
+    apples: u16,
+    nearest_apple: Option<u16>,
+}
+
+// Runs a breadth first search from every living head at once to find the squares, and the apples
+// on them, each snake gets to before any other. A square two snakes reach at the same time goes to
+// the longer one, since it would win the head to head, or to nobody if they're the same length.
+// Snakes only get squares they'd have the health left to get to.
+fn voronoi(game: &Game, vacate_times: &[u16]) -> Vec<Territory> {
+    const CONTESTED: usize = usize::MAX;
+    let lengths: Vec<usize> = game
+        .snakes
+        .iter()
+        .map(|snake| snake.positions.len() + snake.queued as usize)
+        .collect();
+    let mut owners: Vec<Option<usize>> = vec![None; vacate_times.len()];
+    // the owner's health when it gets there
+    let mut healths = vec![0; vacate_times.len()];
+    let mut frontier = Vec::new();
+    for (snake_idx, snake) in game.snakes.iter().enumerate() {
+        if snake.health > 0 {
+            owners[snake.positions[0] as usize] = Some(snake_idx);
+            healths[snake.positions[0] as usize] = snake.health;
+            frontier.push(snake.positions[0]);
+        }
+    }
+    let mut territories = vec![Territory::default(); game.snakes.len()];
+    // squares claimed this step, so ties can be settled before anyone expands from them: the
+    // longest snake to get there, its best health on arrival, and whether a snake just as long got
+    // there too
+    let mut claimed: Vec<u16> = Vec::new();
+    let mut claim_owners: Vec<Option<usize>> = vec![None; vacate_times.len()];
+    let mut claim_healths = vec![0; vacate_times.len()];
+    let mut claim_tied = vec![false; vacate_times.len()];
+    let mut dist = 0;
+    while !frontier.is_empty() {
+        dist += 1;
+        for pos in frontier {
+            let snake_idx = owners[pos as usize].unwrap();
+            for direction in 0..4 {
+                let next = match game.move_position(pos, direction) {
+                    Some(next) => next,
+                    None => continue,
+                };
+                if owners[next as usize].is_some() || vacate_times[next as usize] > dist {
+                    continue;
+                }
+                let health = health_after(game, healths[pos as usize], next);
+                if health == 0 {
+                    continue;
+                }
+                let next = next as usize;
+                match claim_owners[next] {
+                    None => {
+                        claimed.push(next as u16);
+                        claim_owners[next] = Some(snake_idx);
+                        claim_healths[next] = health;
+                    }
+                    Some(owner) if owner == snake_idx => {
+                        claim_healths[next] = health.max(claim_healths[next])
+                    }
+                    Some(owner) if lengths[owner] < lengths[snake_idx] => {
+                        claim_owners[next] = Some(snake_idx);
+                        claim_healths[next] = health;
+                        claim_tied[next] = false;
+                    }
+                    Some(owner) if lengths[owner] == lengths[snake_idx] => claim_tied[next] = true,
+                    Some(_) => {}
+                }
+            }
+        }
+        frontier = Vec::new();
+        for pos in claimed.drain(..) {
+            let pos = pos as usize;
+            let owner = claim_owners[pos].take().unwrap();
+            if std::mem::take(&mut claim_tied[pos]) {
+                // nobody expands from a contested square, but nobody else gets it later either
+                owners[pos] = Some(CONTESTED);
                 continue;
             }
-            other_snake_moves.push(direction);
-            let score = min_rec(
-                game,
-                other_snake_moves,
-                alpha,
-                beta,
-                depth,
-                search_time - start.elapsed().as_millis() as i128,
-            )?;
-            other_snake_moves.pop();
-            if score <= alpha {
-                return Some(alpha);
+            owners[pos] = Some(owner);
+            healths[pos] = claim_healths[pos];
+            let territory = &mut territories[owner];
+            territory.squares += 1;
+            if game.hazards[pos] > 0 {
+                territory.hazard_squares += 1;
             }
-            if score < beta {
-                beta = score;
+            if game.apples[pos] {
+                territory.apples += 1;
+                // squares are claimed in order of distance, so the first apple is the nearest
+                territory.nearest_apple = territory.nearest_apple.or(Some(dist));
             }
+            frontier.push(pos as u16);
         }
     }
-    Some(beta)
+    territories
 }
 
-pub fn eval(game: &Game) -> f64 {
-    fn cast_rays(idx: u16, all_blockers: &Vec<bool>, width: u8, height: u8) -> u16 {
-        let mut total_size = 0;
-        let mut counter = 1;
-        while idx + counter * (width as u16) < width as u16 * height as u16
-            && !all_blockers[(idx + counter * width as u16) as usize]
-        {
-            total_size += 1;
-            counter += 1;
-        }
+// Standing in the sauce means paying to get back out of it, or all our health if there's no way.
+fn sauce_penalty(
+    game: &Game,
+    snake_idx: usize,
+    vacate_times: &[u16],
+    weights: &EvalWeights,
+) -> f64 {
+    let snake = &game.snakes[snake_idx];
+    if game.hazards[snake.positions[0] as usize] == 0 {
+        return 0.0;
+    }
+    hazard_exit_cost(game, snake.positions[0], snake.health, vacate_times).unwrap_or(snake.health)
+        as f64
+        * weights.hazard_exit
+}
 
-        let mut counter = 1;
-        while (idx + counter) % width as u16 != 0 && !all_blockers[(idx + counter) as usize] {
-            total_size += 1;
-            counter += 1;
-        }
+// With nobody else on the board the only goal is staying alive, which mostly means keeping enough
+// room to move around in and not starving.
+fn solo_eval(game: &Game, weights: &EvalWeights) -> f64 {
+    let snake = &game.snakes[0];
+    if snake.health == 0 {
+        return -weights.win;
+    }
+    let vacate_times = vacate_times(game);
+    let area = flood_fill(game, snake.positions[0], snake.health, &vacate_times) as f64;
+    let length = snake.positions.len() as f64 + snake.queued as f64;
+    // a pocket smaller than our body will eventually run out of room
+    let trapped = if area < length { -weights.trapped } else { 0.0 };
+    let squares = game.width as f64 * game.height as f64;
+    trapped - sauce_penalty(game, 0, &vacate_times, weights)
+        + area / squares * weights.area
+        + (snake.health as f64 - weights.health_offset) / weights.health_divisor
+}
 
-        let mut counter = 1;
-        while idx as i16 - counter * width as i16 >= 0
-            && !all_blockers[(idx as i16 - counter * width as i16) as usize]
-        {
-            total_size += 1;
-            counter += 1;
-        }
+// Scores the position for our squad, so snake 0 being dead doesn't matter as long as a teammate
+// is alive.
+pub fn eval(game: &Game, weights: &EvalWeights) -> f64 {
+    // no need to look at the board once the game is decided
+    match decided_score(game, 0, weights) {
+        Some(score) => score,
+        None => eval_all(game, weights)[0],
+    }
+}
 
-        let mut counter = 1;
-        while (idx as i16 - counter + 1) % width as i16 != 0
-            && !all_blockers[(idx as i16 - counter + 1) as usize]
-        {
-            total_size += 1;
-            counter += 1;
+// The score of the position for each snake's squad, the way eval scores it for snake 0's, sharing
+// the work of looking over the board between all of them.
+pub fn eval_all(game: &Game, weights: &EvalWeights) -> Vec<f64> {
+    if game.snakes.len() == 1 {
+        return vec![solo_eval(game, weights)];
+    }
+    let vacate_times = vacate_times(game);
+    let territories = voronoi(game, &vacate_times);
+    let squares = game.width as f64 * game.height as f64;
+    let mut snake_scores = Vec::new();
+    for (snake_idx, snake) in game.snakes.iter().enumerate() {
+        if snake.health == 0 {
+            snake_scores.push(0.0);
+            continue;
         }
-        total_size
+        // territory is small whenever an opponent is close, but a pocket smaller than our body is a
+        // dead end
+        let area = flood_fill(game, snake.positions[0], snake.health, &vacate_times) as f64;
+        let length = snake.positions.len() as f64 + snake.queued as f64;
+        let trapped = if area < length { -weights.trapped } else { 0.0 };
+        let territory = &territories[snake_idx];
+        // from 0 at full health to 1 when starving, and no apple of our own is as bad as the far
+        // side of the board
+        let urgency = (1.0 - snake.health as f64 / 100.0).powi(2);
+        let apple_distance = territory
+            .nearest_apple
+            .unwrap_or(game.width as u16 + game.height as u16) as f64;
+        let hunger = apple_distance * urgency * weights.hunger;
+        // squares in the sauce are worth less
+        let territory_squares =
+            territory.squares as f64 - territory.hazard_squares as f64 * weights.hazard_territory;
+        let sauce = sauce_penalty(game, snake_idx, &vacate_times, weights);
+        snake_scores.push(
+            length * weights.length + territory_squares / squares * weights.territory - sauce
+                + territory.apples as f64 * weights.food
+                - hunger
+                + trapped
+                + area / squares * weights.area
+                + (snake.health as f64 - weights.health_offset) / weights.health_divisor,
+        );
     }
+    (0..game.snakes.len())
+        .map(|snake_idx| {
+            if let Some(score) = decided_score(game, snake_idx, weights) {
+                return score;
+            }
+            // dead teammates don't drag the squad's score down, but dead opponents still count
+            // towards the opponents' average
+            let mut own_score = 0.0;
+            let mut own_count = 0;
+            let mut other_score = 0.0;
+            let mut other_count = 0;
+            for (other_idx, other) in game.snakes.iter().enumerate() {
+                if !game.same_squad(snake_idx, other_idx) {
+                    other_score += snake_scores[other_idx];
+                    other_count += 1;
+                } else if other.health > 0 {
+                    own_score += snake_scores[other_idx];
+                    own_count += 1;
+                }
+            }
+            own_score / own_count as f64 - other_score / other_count as f64
+        })
+        .collect()
+}
 
-    let self_dead = game.snakes[0].health == 0;
-    let others_dead = !game.snakes[1..].iter().any(|snake| snake.health > 0);
+// The score for the given snake's squad if the game is already won, lost or drawn for it.
+fn decided_score(game: &Game, snake_idx: usize, weights: &EvalWeights) -> Option<f64> {
+    if game.snakes.len() == 1 {
+        return None;
+    }
+    let self_dead = squad_dead(game, snake_idx);
+    let others_dead = (0..game.snakes.len())
+        .all(|idx| game.same_squad(snake_idx, idx) || game.snakes[idx].health == 0);
     if self_dead && others_dead {
-        return 0.0;
+        Some(0.0)
     } else if self_dead {
-        return -10000.0;
+        Some(-weights.win)
     } else if others_dead {
-        return 10000.0;
-    }
-    let mut all_blockers = Vec::new();
-    for square in 0..game.width as u16 * game.height as u16 {
-        all_blockers.push(
-            game.snakes
-                .iter()
-                .any(|snake| snake.snake_arr[square as usize]),
-        );
-    }
-    let own_score = game.snakes[0].positions.len() as f64
-        + game.snakes[0].queued as f64
-        + cast_rays(
-            game.snakes[0].positions[0],
-            &all_blockers,
-            game.width,
-            game.height,
-        ) as f64
-            / (game.width as f64 + game.height as f64)
-            * 5.0
-        + (game.snakes[0].health as f64 - 50.0) / 5.0;
-    let mut other_score = 0.0;
-    for other_snake in &game.snakes[1..] {
-        if other_snake.health == 0 {
-            continue;
-        }
-        other_score += other_snake.positions.len() as f64
-            + other_snake.queued as f64
-            + cast_rays(
-                other_snake.positions[0],
-                &all_blockers,
-                game.width,
-                game.height,
-            ) as f64
-                / (game.width as f64 + game.height as f64)
-                * 5.0
-            + (other_snake.health as f64 - 50.0) / 5.0;
+        Some(weights.win)
+    } else {
+        None
     }
-    own_score - other_score / (game.snakes.len() - 1) as f64
 }
 
+#[cfg(test)]
+#[allow(clippy::useless_vec)]
 mod test {
     // Rust says that the import is unused for some reason?
     #[allow(unused_imports)]
     use super::*;
+    use crate::game::Ruleset;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    fn pool() -> ThreadPool {
+        // helper searches make which of two equally good moves gets picked racy
+        ThreadPool::new(1)
+    }
+
+    fn no_deadline() -> Arc<Deadline> {
+        Arc::new(Deadline::at(Instant::now() + Duration::from_secs(3600)))
+    }
+
+    fn context() -> Arc<SearchContext<DefaultEvaluator>> {
+        // big enough for any board used here
+        Arc::new(SearchContext::new(
+            1 << 16,
+            25 * 25,
+            DefaultEvaluator::default(),
+        ))
+    }
 
     #[test]
     fn test_bestmove() {
@@ -328,7 +1006,12 @@ mod test {
         game.move_snakes(&vec![0, 0]);
         game.move_snakes(&vec![3, 0]);
         game.move_snakes(&vec![2, 0]);
-        assert_eq!(best_move(&mut game, 1, i128::MAX).unwrap().1, -10000.0);
+        assert_eq!(
+            best_move(&mut game, 1, None, &pool(), &no_deadline(), &context())
+                .unwrap()
+                .1,
+            -10000.0
+        );
 
         // trap the other snake
         let mut game = Game::new(7, 7);
@@ -339,18 +1022,520 @@ mod test {
         game.move_snakes(&vec![1, 1]);
         game.move_snakes(&vec![1, 1]);
         game.move_snakes(&vec![1, 1]);
-        assert_eq!(best_move(&mut game, 2, i128::MAX).unwrap(), (2, 10000.0));
+        assert_eq!(
+            best_move(&mut game, 2, None, &pool(), &no_deadline(), &context()).unwrap(),
+            (2, 10000.0)
+        );
 
         // avoid losing head-to-head
         let mut game = Game::new(7, 7);
         game.add_start_snake(0);
         game.add_start_snake(6);
         game.snakes[1].queued = 3;
+        // two moves, not three, so the longer snake is one square away instead of already on top
+        // of us
         game.move_snakes(&vec![1, 3]);
         game.move_snakes(&vec![1, 3]);
-        game.move_snakes(&vec![1, 3]);
-        let best_move = best_move(&mut game, 2, i128::MAX).unwrap().0;
+        let prev_state = game.move_snakes(&[1, 3]);
+        assert_eq!(game.snakes[0].health, 0);
+        game.unmove_snake(&prev_state);
+        let best_move = best_move(&mut game, 2, None, &pool(), &no_deadline(), &context())
+            .unwrap()
+            .0;
         assert_ne!(best_move, 1);
         assert_ne!(best_move, 3);
     }
+
+    #[test]
+    fn test_shared_tables() {
+        // the trap from test_bestmove, still found when the tables are reused across iterations
+        let mut game = Game::new(7, 7);
+        game.add_start_snake(9);
+        game.snakes[0].queued = 3;
+        game.add_start_snake(0);
+        game.snakes[1].queued = 3;
+        game.move_snakes(&[1, 1]);
+        game.move_snakes(&[1, 1]);
+        game.move_snakes(&[1, 1]);
+        let shared_context = context();
+        for depth in 1..4 {
+            best_move(
+                &mut game,
+                depth,
+                None,
+                &pool(),
+                &no_deadline(),
+                &shared_context,
+            );
+        }
+        assert_eq!(
+            best_move(&mut game, 2, None, &pool(), &no_deadline(), &shared_context)
+                .unwrap()
+                .1,
+            10000.0
+        );
+    }
+
+    #[test]
+    fn test_wrapped() {
+        // the self trap from test_bestmove, but the walls wrap around
+        let mut game = Game::new(7, 7);
+        game.ruleset = Ruleset::Wrapped;
+        game.add_start_snake(1);
+        game.snakes[0].queued = 10;
+        game.add_start_snake(6);
+        game.move_snakes(&[0, 0]);
+        game.move_snakes(&[3, 0]);
+        game.move_snakes(&[2, 0]);
+        assert!(
+            best_move(&mut game, 1, None, &pool(), &no_deadline(), &context())
+                .unwrap()
+                .1
+                > -10000.0
+        );
+    }
+
+    #[test]
+    fn test_squad() {
+        // a teammate surviving is still a win
+        let mut game = Game::new(7, 7);
+        game.ruleset = Ruleset::Squad;
+        game.settings.squad.shared_elimination = false;
+        game.add_start_snake(0);
+        game.add_start_snake(6);
+        game.snakes[1].squad = 0;
+        game.add_start_snake(48);
+        game.snakes[0].health = 0;
+        game.snakes[2].health = 0;
+        assert_eq!(eval(&game, &EvalWeights::default()), 10000.0);
+        assert_eq!(
+            best_move(&mut game, 2, None, &pool(), &no_deadline(), &context())
+                .unwrap()
+                .1,
+            10000.0
+        );
+    }
+
+    #[test]
+    fn test_solo() {
+        // don't crawl into a pocket that's too small
+        let mut game = Game::new(7, 7);
+        game.add_snake(vec![5, 12, 19, 26, 33, 40, 47], 100, 10, 0);
+        let (direction, score) =
+            best_move(&mut game, 2, None, &pool(), &no_deadline(), &context()).unwrap();
+        assert_eq!(direction, 3);
+        assert!(score < 10000.0);
+    }
+
+    #[test]
+    fn test_deadline() {
+        // iterative deepening gives up once the deadline passes, but keeps the last finished depth
+        let mut game = Game::new(11, 11);
+        game.add_start_snake(0);
+        game.add_start_snake(120);
+        let start = Instant::now();
+        let deadline = Arc::new(Deadline::at(start + Duration::from_millis(100)));
+        let (_, _, depth) = iterative_deepening(
+            &mut game,
+            u8::MAX,
+            &ThreadPool::new(4),
+            &deadline,
+            &context(),
+        );
+        assert!(start.elapsed() < Duration::from_millis(200));
+        assert!(depth >= 1);
+        let deadline = Arc::new(Deadline::at(Instant::now()));
+        assert_eq!(
+            best_move(&mut game, depth, Some(0), &pool(), &deadline, &context()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_busy_pool() {
+        // another game's search has every worker, and this one still gets to search
+        let pool = ThreadPool::new(2);
+        for _ in 0..2 {
+            pool.execute(|| std::thread::sleep(Duration::from_millis(500)));
+        }
+        assert_eq!(pool.idle(), 0);
+        let mut game = Game::new(7, 7);
+        game.add_start_snake(0);
+        game.add_start_snake(48);
+        let game_clone = game.clone();
+        let context = context();
+        let deadline = Arc::new(Deadline::at(Instant::now() + Duration::from_millis(100)));
+        let (direction, _, depth) =
+            iterative_deepening(&mut game, u8::MAX, &pool, &deadline, &context);
+        assert!(depth > 0);
+        assert!(game.legal_moves(0).contains(&direction));
+        // the search ran out of time partway through, and the game is as it was
+        assert_eq!(game, game_clone);
+
+        // and with no time at all it still doesn't pick the wall
+        let deadline = Arc::new(Deadline::at(Instant::now()));
+        let (direction, _, depth) =
+            iterative_deepening(&mut game, u8::MAX, &pool, &deadline, &context);
+        assert_eq!(depth, 0);
+        assert!(game.legal_moves(0).contains(&direction));
+    }
+
+    struct GoWest;
+
+    impl Evaluator for GoWest {
+        fn eval(&self, game: &Game) -> f64 {
+            -((game.snakes[0].positions[0] % game.width as u16) as f64)
+        }
+    }
+
+    #[test]
+    fn test_evaluator() {
+        let mut game = Game::new(7, 7);
+        game.add_start_snake(24);
+        let context = Arc::new(SearchContext::new(1 << 10, 49, GoWest));
+        assert_eq!(
+            best_move(&mut game, 1, None, &pool(), &no_deadline(), &context),
+            Some((3, -2.0))
+        );
+    }
+
+    // GoWest, but it stops the search after a set number of evals.
+    struct StopAfter {
+        evals: AtomicUsize,
+        limit: usize,
+        deadline: Arc<Deadline>,
+    }
+
+    impl Evaluator for StopAfter {
+        fn eval(&self, game: &Game) -> f64 {
+            if self.evals.fetch_add(1, Ordering::Relaxed) + 1 >= self.limit {
+                self.deadline.stop();
+            }
+            GoWest.eval(game)
+        }
+    }
+
+    #[test]
+    fn test_partial_depth() {
+        let mut game = Game::new(7, 7);
+        game.add_start_snake(24);
+        let stop_after = |limit| {
+            let deadline = no_deadline();
+            let context = Arc::new(SearchContext::new(
+                1 << 10,
+                49,
+                StopAfter {
+                    evals: AtomicUsize::new(0),
+                    limit,
+                    deadline: deadline.clone(),
+                },
+            ));
+            (deadline, context)
+        };
+
+        // right finished before the deadline and left didn't, so right is kept even though left
+        // would have been better
+        let (deadline, context) = stop_after(1);
+        assert_eq!(
+            best_move(&mut game, 1, Some(1), &pool(), &deadline, &context),
+            Some((1, -4.0))
+        );
+
+        // the deadline passed partway through right, so nothing at this depth can be trusted
+        let (deadline, context) = stop_after(1);
+        assert_eq!(
+            best_move(&mut game, 2, Some(1), &pool(), &deadline, &context),
+            None
+        );
+    }
+
+    #[test]
+    fn test_weights() {
+        let weights = EvalWeights::load(r#"{"territory": 2.5, "win": 500}"#);
+        assert_eq!(
+            weights,
+            EvalWeights {
+                territory: 2.5,
+                win: 500.0,
+                ..EvalWeights::default()
+            }
+        );
+        let mut game = Game::new(7, 7);
+        game.add_start_snake(0);
+        game.add_start_snake(48);
+        game.snakes[1].health = 0;
+        let evaluator = DefaultEvaluator { weights };
+        assert_eq!(evaluator.eval(&game), 500.0);
+        assert_eq!(evaluator.win(), 500.0);
+    }
+
+    #[test]
+    fn test_area() {
+        // boxed into the corner by a snake that won't move out of the way in time
+        let mut game = Game::new(7, 7);
+        game.add_snake(vec![0, 1, 2], 100, 0, 0);
+        game.add_snake(vec![3, 10, 9, 8, 7], 100, 5, 1);
+        assert!(eval(&game, &EvalWeights::default()) < -500.0);
+
+        // but if it is going to move, there's a way out
+        game.snakes[1].queued = 0;
+        assert!(eval(&game, &EvalWeights::default()) > -500.0);
+
+        // alone, the rest of the board is ours, and it counts for as much as the weight says
+        let mut game = Game::new(7, 7);
+        game.add_start_snake(24);
+        let no_area = EvalWeights {
+            area: 0.0,
+            ..EvalWeights::default()
+        };
+        let difference = eval(&game, &EvalWeights::default()) - eval(&game, &no_area);
+        assert!((difference - 48.0 / 49.0 * 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_voronoi() {
+        // the diagonal between opposite corners is reached by both at once
+        let mut game = Game::new(7, 7);
+        game.add_start_snake(0);
+        game.add_start_snake(48);
+        game.apples[24] = true;
+        game.apples[1] = true;
+        let times = vacate_times(&game);
+        let territories = voronoi(&game, &times);
+        assert_eq!(
+            territories[0],
+            Territory {
+                squares: 20,
+                hazard_squares: 0,
+                apples: 1,
+                nearest_apple: Some(1),
+            }
+        );
+        assert_eq!(
+            territories[1],
+            Territory {
+                squares: 20,
+                hazard_squares: 0,
+                apples: 0,
+                nearest_apple: None,
+            }
+        );
+
+        // the longer snake wins the ties
+        game.snakes[1].queued = 3;
+        let times = vacate_times(&game);
+        let territories = voronoi(&game, &times);
+        assert_eq!(territories[0].squares, 20);
+        assert_eq!(
+            territories[1],
+            Territory {
+                squares: 27,
+                hazard_squares: 0,
+                apples: 1,
+                nearest_apple: Some(6),
+            }
+        );
+
+        // and it doesn't matter who gets there first in the snake order, even when two shorter
+        // snakes reach the square too
+        let mut long_territories = Vec::new();
+        for order in [
+            [0, 1, 2],
+            [0, 2, 1],
+            [1, 0, 2],
+            [1, 2, 0],
+            [2, 0, 1],
+            [2, 1, 0],
+        ] {
+            let starts = [21, 27, 3];
+            let mut game = Game::new(7, 7);
+            for snake_idx in order {
+                game.add_start_snake(starts[snake_idx]);
+            }
+            let long = order.iter().position(|snake_idx| *snake_idx == 2).unwrap();
+            game.snakes[long].queued = 5;
+            let times = vacate_times(&game);
+            long_territories.push(voronoi(&game, &times)[long].clone());
+        }
+        assert!(long_territories
+            .iter()
+            .all(|territory| *territory == long_territories[0]));
+
+        // a hungry snake would rather be near food
+        game.snakes[1].queued = 2;
+        game.apples[1] = false;
+        game.snakes[0].health = 10;
+        let far = eval(&game, &EvalWeights::default());
+        game.apples[24] = false;
+        game.apples[8] = true;
+        let near = eval(&game, &EvalWeights::default());
+        assert!(near > far);
+    }
+
+    #[test]
+    fn test_hazards() {
+        // everything but the two left columns is sauce
+        let mut game = Game::new(7, 7);
+        for pos in 0..49 {
+            if pos % 7 >= 2 {
+                game.hazards[pos] = 1;
+            }
+        }
+        game.add_start_snake(24);
+        game.add_start_snake(0);
+        game.rehash();
+        let times = vacate_times(&game);
+        assert_eq!(hazard_exit_cost(&game, 24, 100, &times), Some(17));
+        let territories = voronoi(&game, &times);
+        assert_eq!(territories[0].squares, 38);
+        assert_eq!(territories[0].hazard_squares, 31);
+        assert_eq!(flood_fill(&game, 24, 100, &times), 48);
+        // on 20 health we can only get one square into the sauce, or through it to the safe side
+        game.snakes[0].health = 20;
+        let times = vacate_times(&game);
+        assert_eq!(voronoi(&game, &times)[0].squares, 8);
+        assert_eq!(flood_fill(&game, 24, 20, &times), 12);
+
+        // head for the way out
+        game.snakes[0].health = 100;
+        game.rehash();
+        assert_eq!(
+            best_move(&mut game, 1, None, &pool(), &no_deadline(), &context())
+                .unwrap()
+                .0,
+            3
+        );
+    }
+
+    #[test]
+    fn test_max_n() {
+        let mut game = Game::new(7, 7);
+        game.add_start_snake(0);
+        game.add_start_snake(6);
+        game.add_start_snake(48);
+        assert_eq!(SearchMode::Auto.resolve(&game), SearchMode::MaxN);
+        assert_eq!(SearchMode::Paranoid.resolve(&game), SearchMode::Paranoid);
+        let weights = EvalWeights::default();
+        let scores = eval_all(&game, &weights);
+        for (snake_idx, score) in scores.iter().enumerate() {
+            assert_eq!(*score, eval(&game.perspective(snake_idx), &weights));
+        }
+
+        // every snake looks out for itself, so nobody walks into the wall
+        let mut context = SearchContext::new(1 << 10, 49, DefaultEvaluator::default());
+        context.mode = SearchMode::MaxN;
+        let context = Arc::new(context);
+        let (direction, score) =
+            best_move(&mut game, 2, None, &pool(), &no_deadline(), &context).unwrap();
+        assert!(direction == 0 || direction == 1);
+        assert!(score > -10000.0);
+
+        // down to two snakes, it's us against them
+        game.snakes[2].health = 0;
+        assert_eq!(SearchMode::Auto.resolve(&game), SearchMode::Paranoid);
+        game.ruleset = Ruleset::Squad;
+        game.snakes[2].health = 100;
+        game.snakes[2].squad = 1;
+        assert_eq!(SearchMode::Auto.resolve(&game), SearchMode::Paranoid);
+    }
+
+    #[test]
+    fn test_best_reply() {
+        let mut game = Game::new(7, 7);
+        game.add_start_snake(1);
+        game.snakes[0].queued = 10;
+        game.add_start_snake(6);
+        // up has the most room, left is a corner and down is off the board
+        assert_eq!(default_move(&game, 0), 0);
+
+        let mut context = SearchContext::new(1 << 10, 49, DefaultEvaluator::default());
+        context.mode = SearchMode::BestReply;
+        let context = Arc::new(context);
+        // self trap, same as test_bestmove
+        game.move_snakes(&[0, 0]);
+        game.move_snakes(&[3, 0]);
+        game.move_snakes(&[2, 0]);
+        assert_eq!(
+            best_move(&mut game, 1, None, &pool(), &no_deadline(), &context)
+                .unwrap()
+                .1,
+            -10000.0
+        );
+
+        // two opponents close enough to matter: paranoid tries every pair of their moves, best
+        // reply only each of their moves on its own
+        let mut game = Game::new(7, 7);
+        for pos in [8, 12, 36, 40] {
+            game.add_start_snake(pos);
+        }
+        let search = |mode| {
+            let mut context = SearchContext::new(
+                1 << 10,
+                49,
+                CountEvals {
+                    evals: AtomicUsize::new(0),
+                },
+            );
+            context.mode = mode;
+            let context = Arc::new(context);
+            let result = best_move(
+                &mut game.clone(),
+                2,
+                None,
+                &pool(),
+                &no_deadline(),
+                &context,
+            );
+            (
+                result.unwrap(),
+                context.evaluator.evals.load(Ordering::Relaxed),
+            )
+        };
+        let ((direction, score), best_reply_evals) = search(SearchMode::BestReply);
+        assert!(game.legal_moves(0).contains(&direction));
+        assert!(score > -10000.0);
+        let (_, paranoid_evals) = search(SearchMode::Paranoid);
+        assert!(best_reply_evals < paranoid_evals);
+    }
+
+    // The default eval, counting the positions it's asked about.
+    struct CountEvals {
+        evals: AtomicUsize,
+    }
+
+    impl Evaluator for CountEvals {
+        fn eval(&self, game: &Game) -> f64 {
+            self.evals.fetch_add(1, Ordering::Relaxed);
+            eval(game, &EvalWeights::default())
+        }
+    }
+
+    #[test]
+    fn test_within_reach() {
+        let mut game = Game::new(11, 11);
+        game.add_start_snake(0);
+        game.add_start_snake(120);
+        game.add_start_snake(3);
+        // opposite corners are 20 moves apart, so it takes 10 turns each to meet
+        assert!(!within_reach(&game, 1, 9));
+        assert!(within_reach(&game, 1, 10));
+        assert!(within_reach(&game, 2, 2));
+        assert!(!within_reach(&game, 2, 1));
+        // around the edges, the corners are right next to each other
+        game.ruleset = Ruleset::Wrapped;
+        assert_eq!(game.distance(0, 120), 2);
+        assert!(within_reach(&game, 1, 1));
+
+        // the far snake just plays its default, and the search still steers clear of the near one
+        game.ruleset = Ruleset::Standard;
+        let context = Arc::new(SearchContext::new(
+            1 << 10,
+            121,
+            DefaultEvaluator::default(),
+        ));
+        let (direction, score) =
+            best_move(&mut game, 2, None, &pool(), &no_deadline(), &context).unwrap();
+        assert_eq!(direction, 0);
+        assert!(score > -10000.0);
+    }
 }
@@ -0,0 +1,84 @@
+use crate::algae::{EvalWeights, SearchMode};
+use std::env;
+use std::str::FromStr;
+use std::thread;
+
+// Settings that can differ between deployments, read from environment variables at startup.
+#[derive(Clone, Debug)]
+pub struct Config {
+    // Size of the search thread pool shared by every game. Defaults to one per CPU.
+    pub threads: usize,
+    // Weights for the evaluation, from ALGAE_WEIGHTS as either JSON or a path to a JSON file.
+    pub weights: EvalWeights,
+    // The search to run, from ALGAE_SEARCH: paranoid (the default), max-n, best-reply or auto.
+    // Max-n goes without pruning, the shared tables and helper threads, so it's only used when
+    // asked for.
+    pub search: SearchMode,
+    // The engine picking moves, from ALGAE_ENGINE: alpha-beta (the default) or mcts.
+    pub engine: Engine,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Engine {
+    // Iterative deepening alpha-beta, searching however ALGAE_SEARCH says.
+    AlphaBeta,
+    // Monte Carlo Tree Search with simultaneous moves.
+    Mcts,
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Engine, String> {
+        match name {
+            "alpha-beta" => Ok(Engine::AlphaBeta),
+            "mcts" => Ok(Engine::Mcts),
+            _ => Err(format!("Unknown engine {}", name)),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Config {
+        let threads = match env::var("ALGAE_THREADS") {
+            Ok(threads) => threads
+                .parse()
+                .expect("ALGAE_THREADS is not a valid number"),
+            Err(_) => thread::available_parallelism()
+                .map(|threads| threads.get())
+                .unwrap_or(1),
+        };
+        let weights = match env::var("ALGAE_WEIGHTS") {
+            Ok(weights) => EvalWeights::load(&weights),
+            Err(_) => EvalWeights::default(),
+        };
+        let search = match env::var("ALGAE_SEARCH") {
+            Ok(search) => search
+                .parse()
+                .expect("ALGAE_SEARCH is not a valid search mode"),
+            Err(_) => SearchMode::Paranoid,
+        };
+        let engine = match env::var("ALGAE_ENGINE") {
+            Ok(engine) => engine.parse().expect("ALGAE_ENGINE is not a valid engine"),
+            Err(_) => Engine::AlphaBeta,
+        };
+        Config {
+            threads,
+            weights,
+            search,
+            engine,
+        }
+    }
+}
+
+// The value after `--name` on the command line, or the default if the flag isn't there.
+pub fn flag<T: FromStr>(args: &[String], name: &str, default: T) -> T {
+    let flag = format!("--{}", name);
+    match args.iter().position(|arg| *arg == flag) {
+        Some(idx) => args
+            .get(idx + 1)
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_else(|| panic!("{} needs a valid value", flag)),
+        None => default,
+    }
+}
@@ -0,0 +1,60 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+// An absolute point in time shared by every thread working on a search. Once it passes, or once
+// someone calls stop, every check afterwards fails, so the whole search unwinds together.
+pub struct Deadline {
+    at: Instant,
+    stopped: AtomicBool,
+}
+
+impl Deadline {
+    pub fn at(at: Instant) -> Deadline {
+        Deadline {
+            at,
+            stopped: AtomicBool::new(false),
+        }
+    }
+
+    // A deadline at the same time that can be stopped early without stopping this one.
+    pub fn child(&self) -> Deadline {
+        Deadline::at(self.at)
+    }
+
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    pub fn expired(&self) -> bool {
+        if self.stopped.load(Ordering::Relaxed) {
+            return true;
+        }
+        if Instant::now() >= self.at {
+            self.stop();
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_deadline() {
+        let deadline = Deadline::at(Instant::now() + Duration::from_millis(20));
+        assert!(!deadline.expired());
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(deadline.expired());
+
+        let deadline = Deadline::at(Instant::now() + Duration::from_secs(60));
+        let child = deadline.child();
+        child.stop();
+        assert!(child.expired());
+        assert!(!deadline.expired());
+        deadline.stop();
+        assert!(deadline.expired());
+    }
+}
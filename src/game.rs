@@ -1,3 +1,5 @@
+use serde::Deserialize;
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Game {
     // Each snake has fields representing the squares each section is on, an snake_array representing
@@ -6,9 +8,16 @@ pub struct Game {
     pub snakes: Vec<Snake>,
     // TODO check perf for hashset type storage
     pub apples: Vec<bool>,
-    pub hazards: Vec<bool>,
+    // Hazards can be stacked on the same square, so this is the number of hazards on each one.
+    pub hazards: Vec<u8>,
     pub width: u8,
     pub height: u8,
+    pub ruleset: Ruleset,
+    pub settings: Settings,
+    pub turn: u32,
+    // Zobrist hash of the position, kept up to date by move_snakes and unmove_snake. Call rehash
+    // after changing any of the fields above directly.
+    pub hash: u64,
 }
 
 impl Game {
@@ -16,26 +25,130 @@ impl Game {
         Game {
             snakes: Vec::new(),
             apples: vec![false; width as usize * height as usize],
-            hazards: vec![false; width as usize * height as usize],
+            hazards: vec![0; width as usize * height as usize],
             width,
             height,
+            ruleset: Ruleset::Standard,
+            settings: Settings::default(),
+            turn: 0,
+            hash: 0,
         }
     }
 
-    pub fn move_snakes(&mut self, directions: &Vec<u8>) -> ChangedState {
+    pub fn rehash(&mut self) {
+        let mut hash = 0;
+        for (snake_idx, snake) in self.snakes.iter().enumerate() {
+            hash ^= snake_key(snake_idx, snake);
+            for pos in &snake.positions {
+                hash ^= body_key(snake_idx, *pos);
+            }
+        }
+        for pos in 0..self.apples.len() {
+            if self.apples[pos] {
+                hash ^= apple_key(pos as u16);
+            }
+            if self.hazards[pos] > 0 {
+                hash ^= hazard_key(pos as u16, self.hazards[pos]);
+            }
+        }
+        self.hash = hash;
+    }
+
+    // Head position, health and queued growth change on nearly every move, so they're hashed
+    // together per snake and toggled out and back in around every move and unmove.
+    fn toggle_snake_keys(&mut self) {
+        for (snake_idx, snake) in self.snakes.iter().enumerate() {
+            self.hash ^= snake_key(snake_idx, snake);
+        }
+    }
+
+    // Returns the square one step from pos in the given direction, or None if it's off the board.
+    // In wrapped games the board is a torus, so every direction has a square.
+    pub fn move_position(&self, pos: u16, direction: u8) -> Option<u16> {
+        let width = self.width as i16;
+        let height = self.height as i16;
+        let x = pos as i16 % width;
+        let y = pos as i16 / width;
+        let (x, y) = match direction {
+            0 => (x, y + 1),
+            1 => (x + 1, y),
+            2 => (x, y - 1),
+            3 => (x - 1, y),
+            _ => panic!("Invalid direction"),
+        };
+        if self.ruleset == Ruleset::Wrapped {
+            Some((y.rem_euclid(height) * width + x.rem_euclid(width)) as u16)
+        } else if x < 0 || x >= width || y < 0 || y >= height {
+            None
+        } else {
+            Some((y * width + x) as u16)
+        }
+    }
+
+    // Number of moves between two squares on an empty board, going around the edges in wrapped.
+    pub fn distance(&self, a: u16, b: u16) -> u16 {
+        let width = self.width as u16;
+        let height = self.height as u16;
+        let dx = (a % width).abs_diff(b % width);
+        let dy = (a / width).abs_diff(b / width);
+        if self.ruleset == Ruleset::Wrapped {
+            dx.min(width - dx) + dy.min(height - dy)
+        } else {
+            dx + dy
+        }
+    }
+
+    // Directions the snake can move in without immediately hitting a wall or its own body.
+    pub fn legal_moves(&self, snake_idx: usize) -> Vec<u8> {
+        let snake = &self.snakes[snake_idx];
+        let mut moves = Vec::new();
+        for direction in 0..4 {
+            if snake.positions.len() == 1 && direction == 2 {
+                continue;
+            }
+            let new_head = match self.move_position(snake.positions[0], direction) {
+                Some(new_head) => new_head,
+                None => continue,
+            };
+            let tail_moves = snake.queued == 0 && !self.ruleset.grows_every_turn();
+            if snake.positions.len() > 1
+                && (new_head != snake.positions[snake.positions.len() - 1] || !tail_moves)
+                && snake.snake_arr[new_head as usize]
+            {
+                continue;
+            }
+            moves.push(direction);
+        }
+        moves
+    }
+
+    // Whether the two snakes are on the same squad. Every snake is on its own squad outside of
+    // squad games.
+    pub fn same_squad(&self, snake_idx: usize, other_idx: usize) -> bool {
+        snake_idx == other_idx
+            || (self.ruleset == Ruleset::Squad
+                && self.snakes[snake_idx].squad == self.snakes[other_idx].squad)
+    }
+
+    pub fn move_snakes(&mut self, directions: &[u8]) -> ChangedState {
         let mut prev_healths = Vec::new();
         let mut tail_pos = Vec::new();
         let mut hit_inaccessible = Vec::new();
-        let mut was_queued = Vec::new();
+        let mut kept_tail = Vec::new();
+        let mut prev_queued = Vec::new();
         let mut eaten_apples = Vec::new();
+        self.toggle_snake_keys();
         for snake_idx in 0..self.snakes.len() {
+            let moved_head =
+                self.move_position(self.snakes[snake_idx].positions[0], directions[snake_idx]);
             let snake = &mut self.snakes[snake_idx];
 
             // store for unmove
             prev_healths.push(snake.health);
             tail_pos.push(snake.positions[snake.positions.len() - 1]);
             hit_inaccessible.push(false);
-            was_queued.push(snake.queued > 0);
+            kept_tail.push(snake.queued > 0 || self.ruleset.grows_every_turn());
+            prev_queued.push(snake.queued);
             eaten_apples.push(false);
 
             // skip if dead
@@ -44,56 +157,52 @@ impl Game {
             }
 
             // get new head position
-            let direction = directions[snake_idx];
-            let new_head_signed = match direction {
-                0 => snake.positions[0] as i16 + self.width as i16,
-                1 => snake.positions[0] as i16 + 1,
-                2 => snake.positions[0] as i16 - self.width as i16,
-                3 => snake.positions[0] as i16 - 1,
-                _ => panic!("Invalid direction"),
-            };
-            let new_head = if new_head_signed < 0
-                || new_head_signed >= self.width as i16 * self.height as i16
-                || (new_head_signed % self.width as i16 == 0 && direction == 1)
-                || ((new_head_signed + 1) % self.width as i16 == 0 && direction == 3)
-            {
-                snake.health = 0;
-                hit_inaccessible[snake_idx] = true;
-                continue;
-            } else {
-                new_head_signed as u16
+            let new_head = match moved_head {
+                Some(new_head) => new_head,
+                None => {
+                    snake.health = 0;
+                    hit_inaccessible[snake_idx] = true;
+                    continue;
+                }
             };
 
             // move snake
-            let end_subtract = if snake.queued == 0 { 1 } else { 0 };
-            if snake.positions[..snake.positions.len() - end_subtract].iter().any(|pos| *pos == new_head) {
+            let end_subtract = if kept_tail[snake_idx] { 0 } else { 1 };
+            if snake.positions[..snake.positions.len() - end_subtract].contains(&new_head) {
                 hit_inaccessible[snake_idx] = true;
                 snake.health = 0;
                 continue;
             } else {
-                if snake.queued == 0 {
+                if !kept_tail[snake_idx] {
                     let last = snake.positions.pop().unwrap();
                     snake.snake_arr[last as usize] = false;
-                } else {
+                    self.hash ^= body_key(snake_idx, last);
+                } else if !self.ruleset.grows_every_turn() {
                     snake.queued -= 1;
                 }
                 snake.positions.insert(0, new_head);
                 snake.snake_arr[new_head as usize] = true;
+                self.hash ^= body_key(snake_idx, new_head);
+            }
+            if self.ruleset.drains_health() {
+                snake.health -= 1;
             }
-            snake.health -= 1;
 
             // eat apple if available
             if self.apples[new_head as usize] {
                 snake.health = 100;
                 snake.queued += 1;
                 self.apples[new_head as usize] = false;
+                self.hash ^= apple_key(new_head);
                 eaten_apples[snake_idx] = true;
             }
 
             // subtract health if head in hazard sauce
-            if self.hazards[new_head as usize] {
-                if snake.health > 15 {
-                    snake.health -= 15;
+            if self.hazards[new_head as usize] > 0 {
+                let damage = self.hazards[new_head as usize] as u16
+                    * self.settings.hazard_damage_per_turn as u16;
+                if (snake.health as u16) > damage {
+                    snake.health -= damage as u8;
                 } else {
                     snake.health = 0;
                     continue;
@@ -126,21 +235,76 @@ impl Game {
                 } else if self.snakes[collide_snake_idx].positions[1..]
                     .iter()
                     .any(|pos| *pos == self.snakes[snake_idx].positions[0])
+                    && !(self.settings.squad.allow_body_collisions
+                        && self.same_squad(snake_idx, collide_snake_idx))
                 {
                     self.snakes[snake_idx].health = 0;
                 }
             }
         }
+
+        if self.ruleset == Ruleset::Squad {
+            self.share_squad_state();
+        }
+        self.toggle_snake_keys();
+        self.turn += 1;
+        // we can't know which side the server will pick, so every side shrinks
+        let added_hazards = if self.shrinks_this_turn() {
+            self.shrink_map(&[0, 1, 2, 3])
+        } else {
+            Vec::new()
+        };
         ChangedState {
             prev_healths,
             tail_pos,
             hit_inaccessible,
-            was_queued,
+            kept_tail,
+            prev_queued,
             eaten_apples,
+            added_hazards,
+        }
+    }
+
+    // Applies the squad settings that make teammates share their fate. Everything changed here is
+    // either health or queued growth, which unmove_snake restores wholesale.
+    fn share_squad_state(&mut self) {
+        for snake_idx in 0..self.snakes.len() {
+            let squad: Vec<usize> = (0..self.snakes.len())
+                .filter(|idx| self.same_squad(snake_idx, *idx))
+                .collect();
+            if self.settings.squad.shared_elimination
+                && squad.iter().any(|idx| self.snakes[*idx].health == 0)
+            {
+                self.snakes[snake_idx].health = 0;
+            }
+            if self.snakes[snake_idx].health == 0 {
+                continue;
+            }
+            let alive: Vec<&Snake> = squad
+                .iter()
+                .map(|idx| &self.snakes[*idx])
+                .filter(|snake| snake.health > 0)
+                .collect();
+            let max_health = alive.iter().map(|snake| snake.health).max().unwrap();
+            let max_length = alive
+                .iter()
+                .map(|snake| snake.positions.len() + snake.queued as usize)
+                .max()
+                .unwrap();
+            let snake = &mut self.snakes[snake_idx];
+            if self.settings.squad.shared_health {
+                snake.health = max_health;
+            }
+            if self.settings.squad.shared_length {
+                snake.queued += (max_length - snake.positions.len() - snake.queued as usize) as u8;
+            }
         }
     }
 
     pub fn unmove_snake(&mut self, prev_state: &ChangedState) {
+        self.clear_hazards(&prev_state.added_hazards);
+        self.turn -= 1;
+        self.toggle_snake_keys();
         for snake_idx in 0..self.snakes.len() {
             let snake = &mut self.snakes[snake_idx];
             if prev_state.prev_healths[snake_idx] == 0 {
@@ -154,37 +318,162 @@ impl Game {
             snake.snake_arr[prev_state.tail_pos[snake_idx] as usize] = true;
             let head = snake.positions[0];
             snake.positions.remove(0);
-            if !prev_state.was_queued[snake_idx] {
+            self.hash ^= body_key(snake_idx, head);
+            if !prev_state.kept_tail[snake_idx] {
                 snake.positions.push(prev_state.tail_pos[snake_idx]);
-            } else {
-                snake.queued += 1;
+                self.hash ^= body_key(snake_idx, prev_state.tail_pos[snake_idx]);
             }
+            snake.queued = prev_state.prev_queued[snake_idx];
             if prev_state.eaten_apples[snake_idx] {
                 self.apples[head as usize] = true;
-                snake.queued -= 1;
+                self.hash ^= apple_key(head);
+            }
+        }
+        self.toggle_snake_keys();
+    }
+
+    // In royale games the safe area shrinks every few turns, starting from the first shrink turn.
+    fn shrinks_this_turn(&self) -> bool {
+        let every = self.settings.royale.shrink_every_n_turns;
+        self.ruleset == Ruleset::Royale && every > 0 && self.turn.is_multiple_of(every)
+    }
+
+    // Fills the given sides (as directions) of the smallest rectangle around every square without
+    // sauce with sauce, returning the squares that were filled.
+    fn shrink_map(&mut self, sides: &[u8]) -> Vec<u16> {
+        let width = self.width as u16;
+        let safe: Vec<u16> = (0..self.hazards.len() as u16)
+            .filter(|pos| self.hazards[*pos as usize] == 0)
+            .collect();
+        let (min_x, max_x, min_y, max_y) = match (
+            safe.iter().map(|pos| pos % width).min(),
+            safe.iter().map(|pos| pos % width).max(),
+            safe.iter().map(|pos| pos / width).min(),
+            safe.iter().map(|pos| pos / width).max(),
+        ) {
+            (Some(min_x), Some(max_x), Some(min_y), Some(max_y)) => (min_x, max_x, min_y, max_y),
+            _ => return Vec::new(),
+        };
+        let mut added = Vec::new();
+        for pos in safe {
+            let (x, y) = (pos % width, pos / width);
+            let on_side = sides.iter().any(|side| match side {
+                0 => y == max_y,
+                1 => x == max_x,
+                2 => y == min_y,
+                _ => x == min_x,
+            });
+            if on_side {
+                self.hazards[pos as usize] = 1;
+                self.hash ^= hazard_key(pos, 1);
+                added.push(pos);
             }
         }
+        added
     }
 
-    pub fn add_snake(&mut self, positions: Vec<u16>, snake_arr: Vec<bool>, health: u8, queued: u8) {
-        self.snakes.push(Snake {
+    fn clear_hazards(&mut self, positions: &[u16]) {
+        for pos in positions {
+            self.hazards[*pos as usize] = 0;
+            self.hash ^= hazard_key(*pos, 1);
+        }
+    }
+
+    // The real server only shrinks one side at a time, picked at random. Swaps the every side
+    // shrink move_snakes predicted for the move that returned prev_state for just the given side,
+    // and updates prev_state to match so the move can still be unmade.
+    pub fn shrink_side(&mut self, prev_state: &mut ChangedState, side: u8) {
+        if prev_state.added_hazards.is_empty() {
+            return;
+        }
+        self.clear_hazards(&prev_state.added_hazards);
+        prev_state.added_hazards = self.shrink_map(&[side]);
+    }
+
+    // The game ends once at most one squad is left standing, or in a solo game once the snake dies.
+    pub fn is_over(&self) -> bool {
+        let living: Vec<usize> = (0..self.snakes.len())
+            .filter(|idx| self.snakes[*idx].health > 0)
+            .collect();
+        if self.snakes.len() == 1 {
+            return living.is_empty();
+        }
+        living.iter().all(|idx| self.same_squad(*idx, living[0]))
+    }
+
+    // A copy of the game from the given snake's point of view: it's moved to index 0 with its
+    // teammates right after it, which is the order the search expects.
+    pub fn perspective(&self, snake_idx: usize) -> Game {
+        let mut order: Vec<usize> = (0..self.snakes.len()).collect();
+        order.sort_by_key(|idx| (*idx != snake_idx, !self.same_squad(snake_idx, *idx)));
+        let mut game = self.clone();
+        game.snakes = order.iter().map(|idx| self.snakes[*idx].clone()).collect();
+        game.rehash();
+        game
+    }
+
+    // Adds a snake with its body on the given squares, head first.
+    pub fn add_snake(&mut self, positions: Vec<u16>, health: u8, queued: u8, squad: u8) {
+        let mut snake_arr = vec![false; self.width as usize * self.height as usize];
+        for pos in &positions {
+            snake_arr[*pos as usize] = true;
+        }
+        self.push_snake(Snake {
             positions,
             snake_arr,
             health,
             queued,
+            squad,
         });
     }
 
     pub fn add_start_snake(&mut self, position: u16) {
         let mut snake_arr = vec![false; self.width as usize * self.height as usize];
         snake_arr[position as usize] = true;
-        self.snakes.push(Snake {
+        self.push_snake(Snake {
             positions: vec![position],
             snake_arr,
             health: 100,
             queued: 2,
+            squad: self.snakes.len() as u8,
         });
     }
+
+    fn push_snake(&mut self, snake: Snake) {
+        let snake_idx = self.snakes.len();
+        self.hash ^= snake_key(snake_idx, &snake);
+        for pos in &snake.positions {
+            self.hash ^= body_key(snake_idx, *pos);
+        }
+        self.snakes.push(snake);
+    }
+}
+
+// SplitMix64's output function, which spreads a few small numbers into a well-mixed key without
+// needing a table of random numbers.
+fn zobrist_key(kind: u64, idx: u64, value: u64) -> u64 {
+    let mut key = ((kind << 48) | (idx << 32) | value).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    key = (key ^ (key >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    key = (key ^ (key >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    key ^ (key >> 31)
+}
+
+fn body_key(snake_idx: usize, pos: u16) -> u64 {
+    zobrist_key(0, snake_idx as u64, pos as u64)
+}
+
+fn snake_key(snake_idx: usize, snake: &Snake) -> u64 {
+    let state =
+        (snake.positions[0] as u64) << 16 | (snake.health as u64) << 8 | snake.queued as u64;
+    zobrist_key(1, snake_idx as u64, state)
+}
+
+fn apple_key(pos: u16) -> u64 {
+    zobrist_key(2, 0, pos as u64)
+}
+
+fn hazard_key(pos: u16, count: u8) -> u64 {
+    zobrist_key(3, count as u64, pos as u64)
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -193,6 +482,102 @@ pub struct Snake {
     pub snake_arr: Vec<bool>,
     pub health: u8,
     pub queued: u8,
+    pub squad: u8,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Ruleset {
+    Standard,
+    // Snakes leaving one edge of the board come back in on the opposite edge.
+    Wrapped,
+    // Snakes grow every turn and never lose health.
+    Constrictor,
+    // Snakes play in teams, which share their fate depending on the squad settings.
+    Squad,
+    // Hazard sauce closes in from one side of the board every few turns.
+    Royale,
+}
+
+impl Ruleset {
+    // Takes the ruleset name as sent by the Battlesnake API; unknown names play as standard.
+    pub fn from_name(name: &str) -> Ruleset {
+        match name {
+            "wrapped" => Ruleset::Wrapped,
+            "constrictor" => Ruleset::Constrictor,
+            "squad" => Ruleset::Squad,
+            "royale" => Ruleset::Royale,
+            _ => Ruleset::Standard,
+        }
+    }
+
+    // Whether every snake grows by one each turn regardless of eating.
+    pub fn grows_every_turn(self) -> bool {
+        self == Ruleset::Constrictor
+    }
+
+    // Whether health drops by one each turn.
+    pub fn drains_health(self) -> bool {
+        self != Ruleset::Constrictor
+    }
+}
+
+// Mirrors the ruleset settings object sent by the Battlesnake API, so it can be deserialized
+// straight from a request. Missing fields fall back to the defaults below.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Settings {
+    // percent chance of spawning a new apple each turn
+    pub food_spawn_chance: u8,
+    pub minimum_food: u8,
+    pub hazard_damage_per_turn: u8,
+    pub royale: RoyaleSettings,
+    pub squad: SquadSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            food_spawn_chance: 15,
+            minimum_food: 1,
+            hazard_damage_per_turn: 15,
+            royale: RoyaleSettings::default(),
+            squad: SquadSettings::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RoyaleSettings {
+    pub shrink_every_n_turns: u32,
+}
+
+impl Default for RoyaleSettings {
+    fn default() -> RoyaleSettings {
+        RoyaleSettings {
+            shrink_every_n_turns: 25,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SquadSettings {
+    pub allow_body_collisions: bool,
+    pub shared_elimination: bool,
+    pub shared_health: bool,
+    pub shared_length: bool,
+}
+
+impl Default for SquadSettings {
+    fn default() -> SquadSettings {
+        SquadSettings {
+            allow_body_collisions: true,
+            shared_elimination: true,
+            shared_health: true,
+            shared_length: true,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -200,10 +585,14 @@ pub struct ChangedState {
     prev_healths: Vec<u8>,
     tail_pos: Vec<u16>,
     hit_inaccessible: Vec<bool>,
-    was_queued: Vec<bool>,
+    kept_tail: Vec<bool>,
+    prev_queued: Vec<u8>,
     eaten_apples: Vec<bool>,
+    added_hazards: Vec<u16>,
 }
 
+#[cfg(test)]
+#[allow(clippy::useless_vec)]
 mod test {
     // Rust says that the import is unused for some reason?
     #[allow(unused_imports)]
@@ -275,13 +664,238 @@ mod test {
         let mut game = Game::new(7, 7);
         game.add_start_snake(0);
         game.add_start_snake(6);
-        let mut hazards = vec![false; 49];
+        let mut hazards = vec![0; 49];
         // unrealistic hazard but w/e, shouldn't affect
-        hazards[1] = true;
+        hazards[1] = 1;
         game.hazards = hazards;
         let game_clone = game.clone();
         let prev_state = game.move_snakes(&vec![1, 0]);
         game.unmove_snake(&prev_state);
         assert_eq!(game, game_clone);
+
+        // stacked hazard sauce
+        let mut game = Game::new(7, 7);
+        game.add_start_snake(0);
+        game.add_start_snake(6);
+        game.hazards[1] = 3;
+        game.hazards[7] = 7;
+        let game_clone = game.clone();
+        let prev_state = game.move_snakes(&[1, 0]);
+        assert_eq!(game.snakes[0].health, 54);
+        game.unmove_snake(&prev_state);
+        assert_eq!(game, game_clone);
+        let prev_state = game.move_snakes(&[0, 0]);
+        assert_eq!(game.snakes[0].health, 0);
+        game.unmove_snake(&prev_state);
+        assert_eq!(game, game_clone);
+    }
+
+    #[test]
+    fn test_wrapped() {
+        let mut game = Game::new(7, 7);
+        game.ruleset = Ruleset::Wrapped;
+        assert_eq!(game.move_position(0, 3), Some(6));
+        assert_eq!(game.move_position(0, 2), Some(42));
+        assert_eq!(game.move_position(48, 0), Some(6));
+        assert_eq!(game.move_position(48, 1), Some(42));
+
+        // going off the edge wraps instead of killing
+        game.add_start_snake(0);
+        game.add_start_snake(24);
+        let game_clone = game.clone();
+        let prev_state = game.move_snakes(&[3, 0]);
+        assert_eq!(game.snakes[0].positions[0], 6);
+        assert_eq!(game.snakes[0].health, 99);
+        game.unmove_snake(&prev_state);
+        assert_eq!(game, game_clone);
+    }
+
+    #[test]
+    fn test_constrictor() {
+        let mut game = Game::new(7, 7);
+        game.ruleset = Ruleset::Constrictor;
+        game.add_start_snake(0);
+        game.add_start_snake(6);
+        game.move_snakes(&[0, 0]);
+        game.move_snakes(&[0, 0]);
+        game.move_snakes(&[1, 3]);
+        let game_clone = game.clone();
+        let prev_state = game.move_snakes(&[1, 3]);
+        assert_eq!(game.snakes[0].positions, vec![16, 15, 14, 7, 0]);
+        assert_eq!(game.snakes[0].queued, 2);
+        assert_eq!(game.snakes[0].health, 100);
+        game.unmove_snake(&prev_state);
+        assert_eq!(game, game_clone);
+
+        // the tail never moves out of the way
+        let mut game = Game::new(7, 7);
+        game.ruleset = Ruleset::Constrictor;
+        game.add_snake(vec![8, 1, 0, 7], 100, 0, 0);
+        game.add_start_snake(6);
+        assert!(!game.legal_moves(0).contains(&3));
+        let game_clone = game.clone();
+        let prev_state = game.move_snakes(&[3, 0]);
+        assert_eq!(game.snakes[0].health, 0);
+        game.unmove_snake(&prev_state);
+        assert_eq!(game, game_clone);
+    }
+
+    #[test]
+    fn test_settings() {
+        let settings: Settings = serde_json::from_str(
+            r#"{
+                "foodSpawnChance": 25,
+                "minimumFood": 2,
+                "hazardDamagePerTurn": 30,
+                "royale": { "shrinkEveryNTurns": 10 },
+                "squad": {
+                    "allowBodyCollisions": false,
+                    "sharedElimination": true,
+                    "sharedHealth": false,
+                    "sharedLength": true
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(settings.food_spawn_chance, 25);
+        assert_eq!(settings.royale.shrink_every_n_turns, 10);
+        assert!(!settings.squad.allow_body_collisions);
+        assert!(!settings.squad.shared_health);
+        let settings: Settings = serde_json::from_str("{}").unwrap();
+        assert_eq!(settings, Settings::default());
+
+        let mut game = Game::new(7, 7);
+        game.settings.hazard_damage_per_turn = 30;
+        game.add_start_snake(0);
+        game.add_start_snake(6);
+        game.hazards[1] = 1;
+        game.move_snakes(&[1, 0]);
+        assert_eq!(game.snakes[0].health, 69);
+    }
+
+    #[test]
+    fn test_squad() {
+        let mut game = Game::new(7, 7);
+        game.ruleset = Ruleset::Squad;
+        game.add_start_snake(0);
+        game.add_start_snake(8);
+        game.snakes[1].squad = 0;
+        game.add_start_snake(48);
+        game.apples[29] = true;
+        game.move_snakes(&[0, 0, 2]);
+
+        // teammates can pass through each other, and share health and length
+        let game_clone = game.clone();
+        let prev_state = game.move_snakes(&[1, 0, 2]);
+        assert_eq!(game.snakes[0].health, 98);
+        let prev_state_2 = game.move_snakes(&[0, 0, 2]);
+        assert_eq!(game.snakes[0].health, 100);
+        assert_eq!(game.snakes[0].queued, 1);
+        game.unmove_snake(&prev_state_2);
+        game.unmove_snake(&prev_state);
+        assert_eq!(game, game_clone);
+
+        // one teammate dying takes the other down too
+        let prev_state = game.move_snakes(&[0, 3, 2]);
+        assert_eq!(game.snakes[0].health, 0);
+        assert_eq!(game.snakes[2].health, 98);
+        game.unmove_snake(&prev_state);
+        assert_eq!(game, game_clone);
+
+        // squads only exist in squad games
+        game.ruleset = Ruleset::Standard;
+        let prev_state = game.move_snakes(&[1, 1, 2]);
+        assert_eq!(game.snakes[0].health, 0);
+        game.unmove_snake(&prev_state);
+    }
+
+    #[test]
+    fn test_hash() {
+        let mut game = Game::new(7, 7);
+        game.add_start_snake(0);
+        game.add_start_snake(6);
+        game.apples[8] = true;
+        game.hazards[20] = 2;
+        game.rehash();
+        let game_clone = game.clone();
+        let mut prev_states = Vec::new();
+        for directions in [[0, 0], [1, 3], [0, 0], [3, 2]] {
+            prev_states.push(game.move_snakes(&directions));
+            let hash = game.hash;
+            game.rehash();
+            assert_eq!(game.hash, hash);
+        }
+        while let Some(prev_state) = prev_states.pop() {
+            game.unmove_snake(&prev_state);
+        }
+        assert_eq!(game, game_clone);
+
+        game.move_snakes(&[0, 0]);
+        assert_ne!(game.hash, game_clone.hash);
+    }
+
+    #[test]
+    fn test_game_over() {
+        let mut game = Game::new(7, 7);
+        game.add_start_snake(0);
+        assert!(!game.is_over());
+        game.add_start_snake(6);
+        game.add_start_snake(48);
+        assert!(!game.is_over());
+        game.snakes[1].health = 0;
+        assert!(!game.is_over());
+        game.ruleset = Ruleset::Squad;
+        game.snakes[2].squad = 0;
+        assert!(game.is_over());
+
+        let perspective = game.perspective(2);
+        assert_eq!(perspective.snakes[0], game.snakes[2]);
+        assert_eq!(perspective.snakes[1], game.snakes[0]);
+        assert_eq!(perspective.snakes[2], game.snakes[1]);
+    }
+
+    #[test]
+    fn test_royale() {
+        let mut game = Game::new(7, 7);
+        game.ruleset = Ruleset::Royale;
+        game.settings.royale.shrink_every_n_turns = 2;
+        game.add_start_snake(24);
+        game.move_snakes(&[0]);
+        assert!(game.hazards.iter().all(|hazard| *hazard == 0));
+        let before = game.clone();
+        let state = game.move_snakes(&[1]);
+        // every side closes in, since we can't know which one will
+        assert_eq!(
+            game.hazards.iter().filter(|hazard| **hazard > 0).count(),
+            24
+        );
+        assert_eq!(game.hazards[16], 0);
+        let hash = game.hash;
+        game.rehash();
+        assert_eq!(game.hash, hash);
+        game.unmove_snake(&state);
+        assert_eq!(game, before);
+
+        // the real shrink only takes the one side
+        let mut state = game.move_snakes(&[1]);
+        game.shrink_side(&mut state, 3);
+        assert_eq!(game.hazards.iter().filter(|hazard| **hazard > 0).count(), 7);
+        assert!((0..7).all(|y| game.hazards[y * 7] == 1));
+        let hash = game.hash;
+        game.rehash();
+        assert_eq!(game.hash, hash);
+        // and unmaking the move takes away only that side
+        game.unmove_snake(&state);
+        assert_eq!(game, before);
+        let mut state = game.move_snakes(&[1]);
+        game.shrink_side(&mut state, 3);
+        game.move_snakes(&[1]);
+        let mut state = game.move_snakes(&[0]);
+        game.shrink_side(&mut state, 3);
+        assert_eq!(
+            game.hazards.iter().filter(|hazard| **hazard > 0).count(),
+            14
+        );
+        assert!((0..7).all(|y| game.hazards[y * 7 + 1] == 1));
     }
 }
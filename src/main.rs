@@ -1,16 +1,57 @@
 pub mod algae;
+pub mod config;
+pub mod deadline;
 pub mod game;
+pub mod mcts;
+pub mod ordering;
+pub mod pool;
+pub mod session;
+pub mod simulate;
+pub mod tournament;
+pub mod transposition;
 
-use game::Game;
+use algae::DefaultEvaluator;
+use config::{Config, Engine};
+use deadline::Deadline;
+use game::{Game, Ruleset, Settings};
+use pool::ThreadPool;
 use serde::Deserialize;
 use serde_json::json;
-use std::time::Instant;
-use warp::http::StatusCode;
+use session::Sessions;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use warp::Filter;
 use warp::Rejection;
 
+// Number of transposition table entries, 16 bytes each.
+const TABLE_SIZE: usize = 1 << 20;
+// Milliseconds of the timeout not spent searching, to cover the round trip to the game server.
+const LATENCY_MARGIN: u64 = 150;
+// How long a game can go without a move before its session is freed, in case /end never comes.
+const SESSION_TIMEOUT: Duration = Duration::from_secs(60);
+
 #[tokio::main]
 async fn main() {
+    let config = Config::from_env();
+    // one pool for every game, so concurrent games can't pile up threads
+    let pool = Arc::new(ThreadPool::new(config.threads));
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("simulate") => return simulate::command(&args[2..], &config, &pool),
+        Some("tournament") => return tournament::command(&args[2..], &config, &pool),
+        _ => {}
+    }
+    let sessions = Arc::new(Sessions::default());
+    {
+        let sessions = sessions.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SESSION_TIMEOUT);
+            loop {
+                interval.tick().await;
+                sessions.expire(SESSION_TIMEOUT);
+            }
+        });
+    }
     let index = warp::path::end().map(|| {
         warp::reply::json(&json!({
             "apiversion": "1",
@@ -19,90 +60,183 @@ async fn main() {
             "tail": "block-bum",
         }))
     });
-    // darn iot coffeemakers these days
+    let start_sessions = sessions.clone();
     let start = warp::path("start")
         .and(warp::post())
-        .map(|| warp::reply::with_status("", StatusCode::IM_A_TEAPOT));
+        .and(warp::body::json())
+        .map(move |sent_start: Move| {
+            start_sessions.start(&sent_start.game.id, &sent_start.you.id);
+            println!(
+                "game {} started, {} sessions in progress",
+                sent_start.game.id,
+                start_sessions.count()
+            );
+            warp::reply()
+        });
+    let end_sessions = sessions.clone();
     let end = warp::path("end")
         .and(warp::post())
-        .map(|| warp::reply::with_status("", StatusCode::IM_A_TEAPOT));
+        .and(warp::body::json())
+        .map(move |sent_end: Move| {
+            end_sessions.end(&sent_end.game.id, &sent_end.you.id);
+            warp::reply()
+        });
     let get_move = warp::path("move")
         .and(warp::post())
         .and(warp::body::json())
-        .and_then(|sent_move: Move| async move {
-            let start = Instant::now();
-            println!("request: {:?}", sent_move);
-            let mut game = Game::new(sent_move.board.width, sent_move.board.height);
-            for apple in sent_move.board.food {
-                let x = apple.x;
-                let y = apple.y;
-                game.apples[(y * sent_move.board.width as u16 + x) as usize] = true;
-            }
-            for hazard in sent_move.board.hazards {
-                let x = hazard.x;
-                let y = hazard.y;
-                game.hazards[(y * sent_move.board.width as u16 + x) as usize] = true;
-            }
-            let mut my_positions: Vec<u16> = Vec::new();
-            let mut my_snake_arr =
-                vec![false; sent_move.board.width as usize * sent_move.board.height as usize];
-            let mut my_queued = 0;
-            for pos_idx in 0..sent_move.you.body.len() {
-                let x = sent_move.you.body[pos_idx].x;
-                let y = sent_move.you.body[pos_idx].y;
-                let pos = y * sent_move.board.width as u16 + x;
-                if my_positions.len() > 0 && my_positions[my_positions.len() - 1] == pos {
-                    my_queued += 1;
-                } else {
-                    my_positions.push(pos);
-                    my_snake_arr[pos as usize] = true;
+        .and(warp::any().map(move || pool.clone()))
+        .and(warp::any().map(move || config.clone()))
+        .and(warp::any().map(move || sessions.clone()))
+        .and_then(
+            |sent_move: Move, pool: Arc<ThreadPool>, config: Config, sessions| async move {
+                let start = Instant::now();
+                println!("request: {:?}", sent_move);
+                let sessions: Arc<Sessions> = sessions;
+                let mut session = sessions.take(&sent_move.game.id, &sent_move.you.id);
+                let mut game = Game::new(sent_move.board.width, sent_move.board.height);
+                game.ruleset = Ruleset::from_name(&sent_move.game.ruleset.name);
+                game.settings = sent_move.game.ruleset.settings;
+                game.turn = sent_move.turn;
+                for apple in sent_move.board.food {
+                    let x = apple.x;
+                    let y = apple.y;
+                    game.apples[(y * sent_move.board.width as u16 + x) as usize] = true;
                 }
-            }
-            game.add_snake(my_positions, my_snake_arr, sent_move.you.health, my_queued);
-            for snake in sent_move.board.snakes {
-                if snake == sent_move.you {
-                    continue;
+                for hazard in sent_move.board.hazards {
+                    let x = hazard.x;
+                    let y = hazard.y;
+                    // stacked hazards are sent as the same coordinate multiple times
+                    let hazard = &mut game.hazards[(y * sent_move.board.width as u16 + x) as usize];
+                    *hazard = hazard.saturating_add(1);
                 }
-                let mut positions: Vec<u16> = Vec::new();
-                let mut snake_arr =
-                    vec![false; sent_move.board.width as usize * sent_move.board.height as usize];
-                let mut queued = 0;
-                for pos_idx in 0..snake.body.len() {
-                    let x = snake.body[pos_idx].x;
-                    let y = snake.body[pos_idx].y;
+                let mut squad_names: Vec<String> = Vec::new();
+                let mut squad_id = |squad: &str| {
+                    // outside of squad games the name is empty, so every snake gets its own squad
+                    match squad_names
+                        .iter()
+                        .position(|name| !squad.is_empty() && name == squad)
+                    {
+                        Some(id) => id as u8,
+                        None => {
+                            squad_names.push(squad.to_string());
+                            (squad_names.len() - 1) as u8
+                        }
+                    }
+                };
+                let mut my_positions: Vec<u16> = Vec::new();
+                let mut my_queued = 0;
+                for pos_idx in 0..sent_move.you.body.len() {
+                    let x = sent_move.you.body[pos_idx].x;
+                    let y = sent_move.you.body[pos_idx].y;
                     let pos = y * sent_move.board.width as u16 + x;
-                    if positions.len() > 0 && positions[positions.len() - 1] == pos {
-                        queued += 1;
+                    if !my_positions.is_empty() && my_positions[my_positions.len() - 1] == pos {
+                        my_queued += 1;
                     } else {
-                        positions.push(pos);
-                        snake_arr[pos as usize] = true;
+                        my_positions.push(pos);
                     }
                 }
-                game.add_snake(positions, snake_arr, snake.health, queued);
-            }
-
-            let mut depth = 1;
-            let mut best_move = (0, 0.0);
-            // subtract ms to avoid accidentally taking slightly too long
-            while start.elapsed().as_millis() < sent_move.game.timeout - 375 {
-                let best_move_temp = algae::best_move(
-                    &mut game,
-                    depth,
-                    (sent_move.game.timeout - start.elapsed().as_millis() - 375) as i128,
+                game.add_snake(
+                    my_positions,
+                    sent_move.you.health,
+                    my_queued,
+                    squad_id(&sent_move.you.squad),
                 );
-                match best_move_temp {
-                    Some(best_move_temp) => best_move = best_move_temp,
-                    None => break,
+                let you = &sent_move.you;
+                let mut other_snakes: Vec<Battlesnake> = sent_move
+                    .board
+                    .snakes
+                    .into_iter()
+                    .filter(|snake| snake != you)
+                    .collect();
+                // teammates go right after us, so the search has them move before any opponent does
+                // and everyone else keeps the index they had last turn
+                other_snakes.sort_by_key(|snake| {
+                    (
+                        snake.squad.is_empty() || snake.squad != you.squad,
+                        session
+                            .opponent_ids
+                            .iter()
+                            .position(|id| *id == snake.id)
+                            .unwrap_or(usize::MAX),
+                    )
+                });
+                let opponent_ids: Vec<String> =
+                    other_snakes.iter().map(|snake| snake.id.clone()).collect();
+                for snake in other_snakes {
+                    let mut positions: Vec<u16> = Vec::new();
+                    let mut queued = 0;
+                    for pos_idx in 0..snake.body.len() {
+                        let x = snake.body[pos_idx].x;
+                        let y = snake.body[pos_idx].y;
+                        let pos = y * sent_move.board.width as u16 + x;
+                        if !positions.is_empty() && positions[positions.len() - 1] == pos {
+                            queued += 1;
+                        } else {
+                            positions.push(pos);
+                        }
+                    }
+                    game.add_snake(positions, snake.health, queued, squad_id(&snake.squad));
                 }
-                depth += 1;
-            }
-            println!("{:?}", (best_move.0, best_move.1, depth));
-            let move_int_to_str = ["up", "right", "down", "left"];
-            Ok(warp::reply::json(&json!({
-                "move": move_int_to_str[best_move.0 as usize],
-                "shout": "*aggressively yells*"
-            }))) as Result<_, Rejection>
-        });
+
+                game.rehash();
+
+                // the rest of the timeout is left for the response to make it back to the server
+                let deadline = Arc::new(Deadline::at(
+                    start
+                        + Duration::from_millis(
+                            sent_move.game.timeout.saturating_sub(LATENCY_MARGIN),
+                        ),
+                ));
+                let squares = sent_move.board.width as usize * sent_move.board.height as usize;
+                // the search holds its thread until the deadline, so it gets one of its own instead
+                // of holding up the server's other requests
+                let (game, mut session, best_move) = tokio::task::spawn_blocking(move || {
+                    let best_move = match config.engine {
+                        Engine::AlphaBeta => {
+                            // shared between every depth, and kept for the next turn, so later
+                            // searches can use what earlier ones found
+                            let context = session.context(
+                                config.search.resolve(&game),
+                                TABLE_SIZE,
+                                squares,
+                                &config.weights,
+                            );
+                            session.order_last_moves(&game, &opponent_ids, &context);
+                            algae::iterative_deepening(
+                                &mut game,
+                                u8::MAX,
+                                &pool,
+                                &deadline,
+                                &context,
+                            )
+                        }
+                        Engine::Mcts => {
+                            let evaluator = Arc::new(DefaultEvaluator {
+                                weights: config.weights,
+                            });
+                            let (direction, reward, playouts) =
+                                mcts::best_move(&game, &pool, &deadline, &evaluator);
+                            println!("{} playouts", playouts);
+                            // there is no depth to a tree search
+                            (direction, reward, 0)
+                        }
+                    };
+                    session.opponent_ids = opponent_ids;
+                    (game, session, best_move)
+                })
+                .await
+                .expect("Failed to read from search thread.");
+                println!("{:?}", best_move);
+                let direction = best_move.0;
+                session.previous = Some(game);
+                sessions.put(&sent_move.game.id, &sent_move.you.id, session);
+                let move_int_to_str = ["up", "right", "down", "left"];
+                Ok(warp::reply::json(&json!({
+                    "move": move_int_to_str[direction as usize],
+                    "shout": "*aggressively yells*"
+                }))) as Result<_, Rejection>
+            },
+        );
     let routes = index
         .or(start)
         .or(end)
@@ -126,7 +260,19 @@ struct Move {
 #[derive(Debug, Deserialize)]
 struct SentGame {
     id: String,
-    timeout: u128,
+    ruleset: SentRuleset,
+    timeout: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct SentRuleset {
+    name: String,
+    // parsed so it shows up in the request log, nothing depends on it
+    #[allow(dead_code)]
+    #[serde(default)]
+    version: String,
+    #[serde(default)]
+    settings: Settings,
 }
 
 #[derive(Debug, Deserialize)]
@@ -148,6 +294,8 @@ struct Battlesnake {
     head: Coord,
     length: u16,
     shout: String,
+    #[serde(default)]
+    squad: String,
 }
 
 #[derive(Debug, Deserialize, Eq, PartialEq)]
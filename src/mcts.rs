@@ -0,0 +1,233 @@
+use crate::algae::{self, Evaluator};
+use crate::deadline::Deadline;
+use crate::game::{ChangedState, Game};
+use crate::pool::ThreadPool;
+use crate::simulate::Rng;
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::sync::Arc;
+
+// How many turns a playout runs past the tree before the evaluator scores it.
+const PLAYOUT_DEPTH: u8 = 8;
+// Chance out of 100 that a playout move is default_move instead of a random one.
+const GREEDY_PLAYOUT: u64 = 50;
+// UCB1 exploration constant, for rewards between 0 and 1.
+const EXPLORATION: f64 = 1.4;
+// Eval scores this far apart make about the difference between a coin flip and a sure thing.
+const REWARD_SCALE: f64 = 20.0;
+
+// Move statistics at one position. Every snake keeps its own, and picks its move from them without
+// looking at anyone else's choice (decoupled UCT), which is what simultaneous moves call for.
+struct Node {
+    // Each snake's moves, and the visits and total reward each one has had.
+    moves: Vec<Vec<u8>>,
+    stats: Vec<Vec<(u32, f64)>>,
+    visits: u32,
+    // The position each combination of moves led to.
+    children: HashMap<Vec<u8>, usize>,
+}
+
+impl Node {
+    fn new(game: &Game) -> Node {
+        let moves: Vec<Vec<u8>> = (0..game.snakes.len())
+            .map(|snake_idx| {
+                if game.snakes[snake_idx].health == 0 {
+                    vec![0]
+                } else {
+                    algae::search_moves(game, snake_idx)
+                }
+            })
+            .collect();
+        let stats = moves
+            .iter()
+            .map(|moves| vec![(0, 0.0); moves.len()])
+            .collect();
+        Node {
+            moves,
+            stats,
+            visits: 0,
+            children: HashMap::new(),
+        }
+    }
+
+    // UCB1 over one snake's moves, trying every move once first.
+    fn select(&self, snake_idx: usize) -> usize {
+        let log_visits = (self.visits.max(1) as f64).ln();
+        let mut best = (f64::NEG_INFINITY, 0);
+        for (move_idx, (visits, total)) in self.stats[snake_idx].iter().enumerate() {
+            if *visits == 0 {
+                return move_idx;
+            }
+            let visits = *visits as f64;
+            let ucb = total / visits + EXPLORATION * (log_visits / visits).sqrt();
+            if ucb > best.0 {
+                best = (ucb, move_idx);
+            }
+        }
+        best.1
+    }
+}
+
+// Searches with Monte Carlo Tree Search until the deadline passes, returning the move for snake 0,
+// its average reward between 0 and 1, and how many playouts went into it. The calling thread and
+// any idle workers, up to the size of the pool, each grow their own tree from the same position
+// and the root statistics are added up at the end.
+pub fn best_move<E: Evaluator>(
+    game: &Game,
+    pool: &ThreadPool,
+    deadline: &Arc<Deadline>,
+    evaluator: &Arc<E>,
+) -> (u8, f64, u32) {
+    let (tx, rx) = mpsc::channel();
+    for worker in 1..=(pool.size() - 1).min(pool.idle()) {
+        let mut game = game.clone();
+        let deadline = deadline.clone();
+        let evaluator = evaluator.clone();
+        let tx = tx.clone();
+        pool.execute(move || {
+            let mut rng = Rng::new(game.hash ^ worker as u64);
+            let _ = tx.send(search(&mut game, &mut rng, &deadline, &*evaluator));
+        });
+    }
+    let mut rng = Rng::new(game.hash);
+    let _ = tx.send(search(&mut game.clone(), &mut rng, deadline, &**evaluator));
+    drop(tx);
+    let moves = algae::search_moves(game, 0);
+    let mut stats = vec![(0, 0.0); moves.len()];
+    // a worker that panicked just doesn't contribute
+    for (root_moves, root_stats) in rx {
+        for (direction, (visits, total)) in root_moves.iter().zip(root_stats) {
+            if let Some(move_idx) = moves.iter().position(|other| other == direction) {
+                stats[move_idx].0 += visits;
+                stats[move_idx].1 += total;
+            }
+        }
+    }
+    let playouts = stats.iter().map(|(visits, _)| visits).sum();
+    let (move_idx, (visits, total)) = stats
+        .iter()
+        .enumerate()
+        .max_by_key(|(_, (visits, _))| *visits)
+        .unwrap();
+    let reward = if *visits > 0 {
+        total / *visits as f64
+    } else {
+        0.0
+    };
+    (moves[move_idx], reward, playouts)
+}
+
+// Grows one tree until the deadline, returning snake 0's root moves and their statistics.
+fn search<E: Evaluator>(
+    game: &mut Game,
+    rng: &mut Rng,
+    deadline: &Deadline,
+    evaluator: &E,
+) -> (Vec<u8>, Vec<(u32, f64)>) {
+    let mut nodes = vec![Node::new(game)];
+    while !deadline.expired() {
+        iterate(&mut nodes, game, rng, evaluator);
+    }
+    let root = nodes.swap_remove(0);
+    (
+        root.moves.into_iter().next().unwrap(),
+        root.stats.into_iter().next().unwrap(),
+    )
+}
+
+// One round of selection down the tree, expansion of one new node, a playout from there, and
+// backing the rewards up the path.
+fn iterate<E: Evaluator>(nodes: &mut Vec<Node>, game: &mut Game, rng: &mut Rng, evaluator: &E) {
+    let mut path: Vec<(usize, Vec<usize>)> = Vec::new();
+    let mut states: Vec<ChangedState> = Vec::new();
+    let mut node_idx = 0;
+    while !game.is_over() {
+        let node = &nodes[node_idx];
+        let choices: Vec<usize> = (0..game.snakes.len())
+            .map(|snake_idx| node.select(snake_idx))
+            .collect();
+        let directions: Vec<u8> = choices
+            .iter()
+            .enumerate()
+            .map(|(snake_idx, choice)| node.moves[snake_idx][*choice])
+            .collect();
+        path.push((node_idx, choices));
+        states.push(game.move_snakes(&directions));
+        match nodes[node_idx].children.get(&directions) {
+            Some(child) => node_idx = *child,
+            None => {
+                nodes.push(Node::new(game));
+                let child = nodes.len() - 1;
+                nodes[node_idx].children.insert(directions, child);
+                break;
+            }
+        }
+    }
+    let rewards = playout(game, rng, evaluator);
+    for (node_idx, choices) in path {
+        let node = &mut nodes[node_idx];
+        node.visits += 1;
+        for (snake_idx, choice) in choices.into_iter().enumerate() {
+            node.stats[snake_idx][choice].0 += 1;
+            node.stats[snake_idx][choice].1 += rewards[snake_idx];
+        }
+    }
+    for state in states.iter().rev() {
+        game.unmove_snake(state);
+    }
+}
+
+// Plays a few turns past the tree, mixing default_move with random moves so playouts don't all
+// look the same, then turns every snake's eval into a reward between 0 and 1. The game is left how
+// it was found.
+fn playout<E: Evaluator>(game: &mut Game, rng: &mut Rng, evaluator: &E) -> Vec<f64> {
+    let mut states = Vec::new();
+    for _ in 0..PLAYOUT_DEPTH {
+        if game.is_over() {
+            break;
+        }
+        let directions: Vec<u8> = (0..game.snakes.len())
+            .map(|snake_idx| {
+                if rng.below(100) < GREEDY_PLAYOUT {
+                    algae::default_move(game, snake_idx)
+                } else {
+                    let moves = algae::search_moves(game, snake_idx);
+                    moves[rng.below(moves.len() as u64) as usize]
+                }
+            })
+            .collect();
+        states.push(game.move_snakes(&directions));
+    }
+    let rewards = evaluator
+        .eval_all(game)
+        .into_iter()
+        .map(|score| 0.5 + 0.5 * (score / REWARD_SCALE).tanh())
+        .collect();
+    for state in states.iter().rev() {
+        game.unmove_snake(state);
+    }
+    rewards
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::algae::DefaultEvaluator;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_mcts() {
+        // down is a dead end next to our own body, up is the rest of the board
+        let mut game = Game::new(7, 7);
+        game.add_snake(vec![7, 8, 9, 2, 1], 100, 5, 0);
+        game.add_start_snake(48);
+        game.rehash();
+        let deadline = Arc::new(Deadline::at(Instant::now() + Duration::from_millis(200)));
+        let evaluator = Arc::new(DefaultEvaluator::default());
+        let (direction, reward, playouts) =
+            best_move(&game, &ThreadPool::new(2), &deadline, &evaluator);
+        assert_eq!(direction, 0);
+        assert!(reward > 0.0 && reward < 1.0);
+        assert!(playouts > 0);
+    }
+}
@@ -0,0 +1,119 @@
+use std::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+
+// Killer moves are only kept for this many plies and snakes; anything deeper or further down the
+// snake list is just ordered by history.
+const MAX_PLY: usize = 64;
+const MAX_SNAKES: usize = 16;
+
+// Killer and history heuristics, shared between search threads and carried from one iterative
+// deepening depth to the next so moves that refuted something last time get tried first.
+pub struct MoveOrdering {
+    // The last move that caused a cutoff for each snake at each ply, plus one so 0 means none.
+    killers: Vec<AtomicU8>,
+    // How much each direction has been worth out of each square, indexed by square * 4 + direction.
+    history: Vec<AtomicU32>,
+}
+
+impl MoveOrdering {
+    pub fn new(squares: usize) -> MoveOrdering {
+        MoveOrdering {
+            killers: (0..MAX_PLY * MAX_SNAKES)
+                .map(|_| AtomicU8::new(0))
+                .collect(),
+            history: (0..squares * 4).map(|_| AtomicU32::new(0)).collect(),
+        }
+    }
+
+    // Sorts moves so the hash move comes first, then the killer, then the rest by history score.
+    pub fn order(
+        &self,
+        moves: &mut [u8],
+        head: u16,
+        snake_idx: usize,
+        ply: usize,
+        hash_move: Option<u8>,
+    ) {
+        let killer = self.killer(snake_idx, ply);
+        moves.sort_by_key(|direction| {
+            (
+                Some(*direction) != hash_move,
+                Some(*direction) != killer,
+                u32::MAX
+                    - self.history[head as usize * 4 + *direction as usize].load(Ordering::Relaxed),
+            )
+        });
+    }
+
+    // Records a move that was good enough to cut off the rest of its siblings.
+    pub fn record_cutoff(&self, head: u16, snake_idx: usize, ply: usize, direction: u8, depth: u8) {
+        if ply < MAX_PLY && snake_idx < MAX_SNAKES {
+            self.killers[ply * MAX_SNAKES + snake_idx].store(direction + 1, Ordering::Relaxed);
+        }
+        let depth = depth as u32;
+        self.history[head as usize * 4 + direction as usize]
+            .fetch_add(depth * depth, Ordering::Relaxed);
+    }
+
+    // Makes room for a new move's search: killers are cleared since last move's plies are off by one
+    // from this one's, and history is halved so it follows where the game is now rather than where
+    // it's been.
+    pub fn age(&self) {
+        for killer in &self.killers {
+            killer.store(0, Ordering::Relaxed);
+        }
+        for score in &self.history {
+            score.store(score.load(Ordering::Relaxed) / 2, Ordering::Relaxed);
+        }
+    }
+
+    fn killer(&self, snake_idx: usize, ply: usize) -> Option<u8> {
+        if ply >= MAX_PLY || snake_idx >= MAX_SNAKES {
+            return None;
+        }
+        match self.killers[ply * MAX_SNAKES + snake_idx].load(Ordering::Relaxed) {
+            0 => None,
+            direction => Some(direction - 1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_order() {
+        let ordering = MoveOrdering::new(49);
+        let mut moves = vec![0, 1, 2, 3];
+        ordering.order(&mut moves, 24, 0, 3, None);
+        assert_eq!(moves, vec![0, 1, 2, 3]);
+
+        ordering.record_cutoff(24, 1, 3, 2, 4);
+        ordering.record_cutoff(24, 0, 5, 1, 2);
+        ordering.record_cutoff(24, 0, 3, 3, 1);
+        ordering.order(&mut moves, 24, 0, 3, None);
+        assert_eq!(moves, vec![3, 2, 1, 0]);
+        ordering.order(&mut moves, 24, 0, 3, Some(0));
+        assert_eq!(moves, vec![0, 3, 2, 1]);
+        // history is per square
+        let mut moves = vec![0, 1, 2, 3];
+        ordering.order(&mut moves, 23, 0, 4, None);
+        assert_eq!(moves, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_age() {
+        let ordering = MoveOrdering::new(49);
+        ordering.record_cutoff(24, 0, 3, 0, 1);
+        ordering.record_cutoff(24, 0, 2, 2, 3);
+        ordering.record_cutoff(24, 0, 2, 1, 2);
+        ordering.age();
+        assert_eq!(ordering.killer(0, 2), None);
+        assert_eq!(ordering.history[24 * 4 + 2].load(Ordering::Relaxed), 4);
+        assert_eq!(ordering.history[24 * 4 + 1].load(Ordering::Relaxed), 2);
+        assert_eq!(ordering.history[24 * 4].load(Ordering::Relaxed), 0);
+        let mut moves = vec![0, 1, 2, 3];
+        ordering.order(&mut moves, 24, 0, 2, None);
+        assert_eq!(moves, vec![2, 1, 0, 3]);
+    }
+}
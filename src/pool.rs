@@ -0,0 +1,95 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+// A fixed set of worker threads that live for as long as the pool does, so searches for every
+// game on the server share the same threads instead of spawning their own.
+pub struct ThreadPool {
+    sender: Sender<Job>,
+    size: usize,
+    // Jobs that are queued or running.
+    busy: Arc<AtomicUsize>,
+}
+
+impl ThreadPool {
+    pub fn new(size: usize) -> ThreadPool {
+        let size = size.max(1);
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let busy = Arc::new(AtomicUsize::new(0));
+        for _ in 0..size {
+            let receiver = receiver.clone();
+            let busy = busy.clone();
+            thread::spawn(move || loop {
+                // the lock is only held while waiting for a job, not while running it
+                let job = receiver.lock().unwrap().recv();
+                match job {
+                    // a panicking job shouldn't take a worker down with it
+                    Ok(job) => {
+                        drop(panic::catch_unwind(AssertUnwindSafe(job)));
+                        busy.fetch_sub(1, Ordering::Relaxed);
+                    }
+                    // the pool was dropped
+                    Err(_) => break,
+                }
+            });
+        }
+        ThreadPool { sender, size, busy }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // Workers with nothing to do right now. Another game can take them at any moment, so this is a
+    // hint for how much to hand out, not a promise that it will start right away.
+    pub fn idle(&self) -> usize {
+        self.size.saturating_sub(self.busy.load(Ordering::Relaxed))
+    }
+
+    // Jobs are started in the order they're submitted.
+    pub fn execute<F: FnOnce() + Send + 'static>(&self, job: F) {
+        self.busy.fetch_add(1, Ordering::Relaxed);
+        self.sender
+            .send(Box::new(job))
+            .expect("Thread pool workers are gone.");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pool() {
+        let pool = ThreadPool::new(3);
+        assert_eq!(pool.size(), 3);
+        let (tx, rx) = mpsc::channel();
+        pool.execute(|| panic!("job panicked"));
+        for job in 0..10 {
+            let tx = tx.clone();
+            pool.execute(move || tx.send(job * 2).unwrap());
+        }
+        let mut results: Vec<i32> = (0..10).map(|_| rx.recv().unwrap()).collect();
+        results.sort_unstable();
+        assert_eq!(results, (0..10).map(|job| job * 2).collect::<Vec<i32>>());
+
+        // a worker is only idle once its job is done
+        while pool.idle() < 3 {
+            thread::yield_now();
+        }
+        let (release, wait) = mpsc::channel::<()>();
+        pool.execute(move || {
+            let _ = wait.recv();
+        });
+        assert_eq!(pool.idle(), 2);
+        release.send(()).unwrap();
+        while pool.idle() < 3 {
+            thread::yield_now();
+        }
+    }
+}
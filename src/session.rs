@@ -0,0 +1,295 @@
+use crate::algae::{DefaultEvaluator, EvalWeights, SearchContext, SearchMode};
+use crate::game::Game;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// What we know about one game from the turns before this one.
+pub struct Session {
+    // The search tables from the last move, so the next search starts with what that one found.
+    // None until the first alpha-beta search, or after the mode changes, since scores from one mode
+    // mean nothing to another.
+    context: Option<Arc<SearchContext<DefaultEvaluator>>>,
+    // Opponent ids in the order they were added to the game last turn, so each one keeps its index
+    // and the tables keep applying to it.
+    pub opponent_ids: Vec<String>,
+    // The board last turn, to tell which way everyone went since.
+    pub previous: Option<Game>,
+    last_used: Instant,
+}
+
+impl Session {
+    fn new() -> Session {
+        Session {
+            context: None,
+            opponent_ids: Vec::new(),
+            previous: None,
+            last_used: Instant::now(),
+        }
+    }
+
+    // The search context to use for this move, kept from the last one if it was searched the same
+    // way, with its move ordering aged so last move's doesn't outweigh this one's.
+    pub fn context(
+        &mut self,
+        mode: SearchMode,
+        table_size: usize,
+        squares: usize,
+        weights: &EvalWeights,
+    ) -> Arc<SearchContext<DefaultEvaluator>> {
+        match &self.context {
+            Some(context) if context.mode == mode => {
+                context.ordering.age();
+                context.clone()
+            }
+            _ => {
+                let mut context = SearchContext::new(
+                    table_size,
+                    squares,
+                    DefaultEvaluator {
+                        weights: weights.clone(),
+                    },
+                );
+                context.mode = mode;
+                let context = Arc::new(context);
+                self.context = Some(context.clone());
+                context
+            }
+        }
+    }
+
+    // The direction each snake in the game moved last turn, us first and then the opponents in
+    // the given order, or None if it wasn't on the board last turn.
+    pub fn last_moves(&self, game: &Game, opponent_ids: &[String]) -> Vec<Option<u8>> {
+        let previous = match &self.previous {
+            Some(previous) => previous,
+            None => return vec![None; game.snakes.len()],
+        };
+        (0..game.snakes.len())
+            .map(|snake_idx| {
+                let previous_idx = if snake_idx == 0 {
+                    Some(0)
+                } else {
+                    self.opponent_ids
+                        .iter()
+                        .position(|id| *id == opponent_ids[snake_idx - 1])
+                        .map(|idx| idx + 1)
+                };
+                let previous_head = previous.snakes.get(previous_idx?)?.positions[0];
+                let head = game.snakes[snake_idx].positions[0];
+                (0..4).find(|direction| game.move_position(previous_head, *direction) == Some(head))
+            })
+            .collect()
+    }
+
+    // Has the search try each snake's last move first at the root, since snakes mostly keep doing
+    // what they were doing.
+    pub fn order_last_moves(
+        &self,
+        game: &Game,
+        opponent_ids: &[String],
+        context: &SearchContext<DefaultEvaluator>,
+    ) {
+        for (snake_idx, direction) in self.last_moves(game, opponent_ids).into_iter().enumerate() {
+            if let Some(direction) = direction {
+                let head = game.snakes[snake_idx].positions[0];
+                context
+                    .ordering
+                    .record_cutoff(head, snake_idx, 0, direction, 1);
+            }
+        }
+    }
+}
+
+// A game id and the id of our snake in it, since the server can have several of our snakes in the
+// same game and each one needs its own view of it.
+type Key = (String, String);
+
+fn key(game_id: &str, snake_id: &str) -> Key {
+    (game_id.to_string(), snake_id.to_string())
+}
+
+#[derive(Default)]
+struct Store {
+    sessions: HashMap<Key, Session>,
+    // Games that got /end, with when, so a move still searching when it came doesn't put its
+    // session back afterwards.
+    ended: HashMap<Key, Instant>,
+}
+
+// Sessions of every game in progress, keyed by game id and our snake's id. A move takes its
+// session out while it searches and puts it back afterwards, so the lock is never held across a
+// search.
+#[derive(Default)]
+pub struct Sessions {
+    store: Mutex<Store>,
+}
+
+impl Sessions {
+    // Starts the game over with an empty session.
+    pub fn start(&self, game_id: &str, snake_id: &str) {
+        let key = key(game_id, snake_id);
+        let mut store = self.lock();
+        store.ended.remove(&key);
+        store.sessions.insert(key, Session::new());
+    }
+
+    // The snake's session, or a new one if /start never came through or it expired.
+    pub fn take(&self, game_id: &str, snake_id: &str) -> Session {
+        self.lock()
+            .sessions
+            .remove(&key(game_id, snake_id))
+            .unwrap_or_else(Session::new)
+    }
+
+    // Puts the session back for the next move, unless the game ended while it was out.
+    pub fn put(&self, game_id: &str, snake_id: &str, mut session: Session) {
+        let key = key(game_id, snake_id);
+        let mut store = self.lock();
+        if store.ended.contains_key(&key) {
+            return;
+        }
+        session.last_used = Instant::now();
+        store.sessions.insert(key, session);
+    }
+
+    pub fn end(&self, game_id: &str, snake_id: &str) {
+        let key = key(game_id, snake_id);
+        let mut store = self.lock();
+        store.sessions.remove(&key);
+        store.ended.insert(key, Instant::now());
+    }
+
+    // Frees the sessions of games that haven't had a move in the given time, for when /end never
+    // arrives, and forgets games that ended longer ago than that.
+    pub fn expire(&self, idle: Duration) {
+        let mut store = self.lock();
+        store
+            .sessions
+            .retain(|_, session| session.last_used.elapsed() < idle);
+        store.ended.retain(|_, ended| ended.elapsed() < idle);
+    }
+
+    // How many of our snakes have a session, which can be more than the number of games.
+    pub fn count(&self) -> usize {
+        self.lock().sessions.len()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Store> {
+        // a move that panicked can't have left the map half changed, so its poison doesn't matter
+        self.store
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sessions() {
+        let sessions = Sessions::default();
+        sessions.start("a", "us");
+        sessions.start("b", "us");
+        assert_eq!(sessions.count(), 2);
+
+        // the tables carry over between moves searched the same way
+        let mut session = sessions.take("a", "us");
+        let weights = EvalWeights::default();
+        let context = session.context(SearchMode::Paranoid, 1 << 10, 49, &weights);
+        session.opponent_ids.push("them".to_string());
+        sessions.put("a", "us", session);
+        let mut session = sessions.take("a", "us");
+        assert_eq!(session.opponent_ids, vec!["them".to_string()]);
+        context.ordering.record_cutoff(24, 0, 0, 1, 1);
+        assert!(Arc::ptr_eq(
+            &context,
+            &session.context(SearchMode::Paranoid, 1 << 10, 49, &weights)
+        ));
+        // but last move's killer is gone and its history halved
+        let mut moves = vec![0, 1, 2, 3];
+        context.ordering.order(&mut moves, 24, 0, 0, None);
+        assert_eq!(moves, vec![0, 1, 2, 3]);
+        assert!(!Arc::ptr_eq(
+            &context,
+            &session.context(SearchMode::MaxN, 1 << 10, 49, &weights)
+        ));
+        sessions.put("a", "us", session);
+
+        sessions.end("b", "us");
+        assert_eq!(sessions.count(), 1);
+        // a move still searching when its game ended doesn't bring the session back
+        let session = sessions.take("a", "us");
+        sessions.end("a", "us");
+        sessions.put("a", "us", session);
+        assert_eq!(sessions.count(), 0);
+        sessions.start("a", "us");
+        sessions.put("a", "us", sessions.take("a", "us"));
+        assert_eq!(sessions.count(), 1);
+        // a game nobody started still gets a session
+        assert!(sessions.take("c", "us").opponent_ids.is_empty());
+
+        sessions.expire(Duration::from_secs(60));
+        assert_eq!(sessions.count(), 1);
+        sessions.expire(Duration::ZERO);
+        assert_eq!(sessions.count(), 0);
+    }
+
+    #[test]
+    fn test_shared_game() {
+        // two of our snakes in the same game each keep their own session
+        let sessions = Sessions::default();
+        sessions.start("a", "us");
+        sessions.start("a", "teammate");
+        assert_eq!(sessions.count(), 2);
+
+        let mut ours = sessions.take("a", "us");
+        let teammates = sessions.take("a", "teammate");
+        assert!(teammates.opponent_ids.is_empty());
+        ours.opponent_ids.push("teammate".to_string());
+        sessions.put("a", "us", ours);
+        sessions.put("a", "teammate", teammates);
+        assert_eq!(
+            sessions.take("a", "us").opponent_ids,
+            vec!["teammate".to_string()]
+        );
+        assert!(sessions.take("a", "teammate").opponent_ids.is_empty());
+
+        // one of them ending doesn't end the other
+        sessions.start("a", "us");
+        sessions.start("a", "teammate");
+        sessions.end("a", "teammate");
+        let session = sessions.take("a", "us");
+        sessions.put("a", "us", session);
+        assert_eq!(sessions.count(), 1);
+    }
+
+    #[test]
+    fn test_last_moves() {
+        let mut session = Session::new();
+        let mut previous = Game::new(7, 7);
+        previous.add_snake(vec![24, 17], 100, 0, 0);
+        previous.add_snake(vec![8, 1], 100, 0, 1);
+        previous.add_snake(vec![40, 41], 100, 0, 2);
+        session.previous = Some(previous);
+        session.opponent_ids = vec!["a".to_string(), "b".to_string()];
+
+        // a died, b is now first and c wasn't there before
+        let mut game = Game::new(7, 7);
+        game.add_snake(vec![31, 24, 17], 100, 0, 0);
+        game.add_snake(vec![39, 40, 41], 100, 0, 1);
+        game.add_snake(vec![0], 100, 0, 2);
+        let opponent_ids = vec!["b".to_string(), "c".to_string()];
+        assert_eq!(
+            session.last_moves(&game, &opponent_ids),
+            vec![Some(0), Some(3), None]
+        );
+
+        let context = session.context(SearchMode::Paranoid, 1 << 10, 49, &EvalWeights::default());
+        session.order_last_moves(&game, &opponent_ids, &context);
+        let mut moves = vec![0, 1, 2, 3];
+        context.ordering.order(&mut moves, 39, 1, 0, None);
+        assert_eq!(moves[0], 3);
+    }
+}
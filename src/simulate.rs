@@ -0,0 +1,390 @@
+use crate::algae::{self, DefaultEvaluator, EvalWeights, SearchContext, SearchMode};
+use crate::config::{self, Config, Engine};
+use crate::deadline::Deadline;
+use crate::game::{Game, Ruleset, Settings};
+use crate::mcts;
+use crate::pool::ThreadPool;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Smaller than the server's table, since simulations run a lot of short searches back to back.
+const TABLE_SIZE: usize = 1 << 18;
+
+// SplitMix64, which is plenty random for placing snakes and food and keeps every game
+// reproducible from its seed.
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // A number in 0..n.
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+}
+
+// How the search plays when it's driving a snake.
+#[derive(Clone, Debug)]
+pub struct EngineConfig {
+    pub move_time: Duration,
+    pub max_depth: u8,
+    pub weights: EvalWeights,
+    pub search: SearchMode,
+    pub engine: Engine,
+}
+
+// Called with the game and the snake's index, returns a direction.
+pub type Callback = Box<dyn FnMut(&Game, usize) -> u8>;
+
+// What picks the moves for one snake in a simulated game.
+pub enum Driver {
+    Engine(EngineConfig),
+    Callback(Callback),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Outcome {
+    // The snakes still alive at the end. Empty for a draw, where everyone died on the same turn
+    // or the game hit the turn limit.
+    pub winners: Vec<usize>,
+    pub turns: u32,
+}
+
+// Sets up a game the way the official server does: snakes start one square in from the corners
+// and the middles of the edges, in a random order, each with an apple diagonally next to it on the
+// side away from the center, and one more apple in the center.
+pub fn new_game(width: u8, height: u8, snakes: usize, rng: &mut Rng) -> Game {
+    let mut game = Game::new(width, height);
+    let (w, h) = (width as u16, height as u16);
+    let mut starts = vec![
+        (1, 1),
+        (w - 2, h - 2),
+        (1, h - 2),
+        (w - 2, 1),
+        (w / 2, 1),
+        (w / 2, h - 2),
+        (1, h / 2),
+        (w - 2, h / 2),
+    ];
+    assert!(snakes <= starts.len(), "At most 8 snakes fit on the board.");
+    for idx in (1..starts.len()).rev() {
+        starts.swap(idx, rng.below(idx as u64 + 1) as usize);
+    }
+    let (center_x, center_y) = (w / 2, h / 2);
+    for (x, y) in starts.into_iter().take(snakes) {
+        game.add_start_snake(y * w + x);
+        let away_from_center = |food_x: u16, food_y: u16| {
+            (food_x < x && x < center_x)
+                || (center_x < x && x < food_x)
+                || (food_y < y && y < center_y)
+                || (center_y < y && y < food_y)
+        };
+        let corner = |food_x: u16, food_y: u16| {
+            (food_x == 0 || food_x == w - 1) && (food_y == 0 || food_y == h - 1)
+        };
+        let food: Vec<u16> = [
+            (x - 1, y - 1),
+            (x - 1, y + 1),
+            (x + 1, y - 1),
+            (x + 1, y + 1),
+        ]
+        .iter()
+        .filter(|(food_x, food_y)| {
+            (*food_x, *food_y) != (center_x, center_y)
+                && !game.apples[(food_y * w + food_x) as usize]
+                && away_from_center(*food_x, *food_y)
+                && !corner(*food_x, *food_y)
+        })
+        .map(|(food_x, food_y)| food_y * w + food_x)
+        .collect();
+        if !food.is_empty() {
+            game.apples[food[rng.below(food.len() as u64) as usize] as usize] = true;
+        }
+    }
+    game.apples[(center_y * w + center_x) as usize] = true;
+    game.rehash();
+    game
+}
+
+// Standard food spawning: top up to the minimum, otherwise spawn one apple with the given
+// chance, always on a square without a snake or an apple on it.
+pub fn spawn_food(game: &mut Game, rng: &mut Rng) {
+    let apples = game.apples.iter().filter(|apple| **apple).count();
+    let minimum = game.settings.minimum_food as usize;
+    let spawn = if apples < minimum {
+        minimum - apples
+    } else if rng.below(100) < game.settings.food_spawn_chance as u64 {
+        1
+    } else {
+        0
+    };
+    for _ in 0..spawn {
+        let free: Vec<usize> = (0..game.apples.len())
+            .filter(|pos| {
+                !game.apples[*pos]
+                    && game
+                        .snakes
+                        .iter()
+                        .all(|snake| snake.health == 0 || !snake.snake_arr[*pos])
+            })
+            .collect();
+        if free.is_empty() {
+            break;
+        }
+        game.apples[free[rng.below(free.len() as u64) as usize]] = true;
+    }
+    game.rehash();
+}
+
+// Plays the game out with one driver per snake, spawning food with the given rng, until it's over
+// or max_turns have been played.
+pub fn play(
+    game: &mut Game,
+    drivers: &mut [Driver],
+    pool: &ThreadPool,
+    rng: &mut Rng,
+    max_turns: u32,
+) -> Outcome {
+    assert_eq!(drivers.len(), game.snakes.len());
+    spawn_food(game, rng);
+    while !game.is_over() && game.turn < max_turns {
+        let mut directions = Vec::new();
+        for (snake_idx, driver) in drivers.iter_mut().enumerate() {
+            if game.snakes[snake_idx].health == 0 {
+                directions.push(0);
+                continue;
+            }
+            directions.push(match driver {
+                Driver::Engine(engine) => engine_move(game, snake_idx, engine, pool),
+                Driver::Callback(callback) => callback(game, snake_idx),
+            });
+        }
+        let mut state = game.move_snakes(&directions);
+        game.shrink_side(&mut state, rng.below(4) as u8);
+        spawn_food(game, rng);
+    }
+    let winners = if game.is_over() {
+        (0..game.snakes.len())
+            .filter(|idx| game.snakes[*idx].health > 0)
+            .collect()
+    } else {
+        Vec::new()
+    };
+    Outcome {
+        winners,
+        turns: game.turn,
+    }
+}
+
+fn engine_move(game: &Game, snake_idx: usize, engine: &EngineConfig, pool: &ThreadPool) -> u8 {
+    let mut game = game.perspective(snake_idx);
+    let evaluator = DefaultEvaluator {
+        weights: engine.weights.clone(),
+    };
+    let deadline = Arc::new(Deadline::at(Instant::now() + engine.move_time));
+    match engine.engine {
+        Engine::AlphaBeta => {
+            let mut context = SearchContext::new(
+                TABLE_SIZE,
+                game.width as usize * game.height as usize,
+                evaluator,
+            );
+            context.mode = engine.search.resolve(&game);
+            let context = Arc::new(context);
+            algae::iterative_deepening(&mut game, engine.max_depth, pool, &deadline, &context).0
+        }
+        Engine::Mcts => mcts::best_move(&game, pool, &deadline, &Arc::new(evaluator)).0,
+    }
+}
+
+// `algae simulate`: plays one game between engines with the same settings and prints the result.
+pub fn command(args: &[String], config: &Config, pool: &ThreadPool) {
+    let seed = config::flag(args, "seed", 0);
+    let snakes = config::flag(args, "snakes", 2);
+    let width = config::flag(args, "width", 11);
+    let height = config::flag(args, "height", 11);
+    let ruleset: String = config::flag(args, "ruleset", "standard".to_string());
+    let engine = EngineConfig {
+        move_time: Duration::from_millis(config::flag(args, "move-time", 100)),
+        max_depth: config::flag(args, "depth", u8::MAX),
+        weights: weights_flag(args, "weights", config),
+        search: config::flag(args, "search", config.search),
+        engine: config::flag(args, "engine", config.engine),
+    };
+    let max_turns = config::flag(args, "max-turns", 1000);
+    let hazards: String = config::flag(args, "hazards", String::new());
+    let squad_size = config::flag(args, "squad-size", 2);
+
+    let mut rng = Rng::new(seed);
+    let mut game = new_game(width, height, snakes, &mut rng);
+    game.ruleset = Ruleset::from_name(&ruleset);
+    game.settings = settings_flags(args, game.settings);
+    for pos in parse_hazards(&hazards, width) {
+        game.hazards[pos as usize] = game.hazards[pos as usize].saturating_add(1);
+    }
+    if game.ruleset == Ruleset::Squad {
+        assign_squads(&mut game, squad_size);
+    }
+    game.rehash();
+    let mut drivers: Vec<Driver> = (0..snakes)
+        .map(|_| Driver::Engine(engine.clone()))
+        .collect();
+    let outcome = play(&mut game, &mut drivers, pool, &mut rng, max_turns);
+    println!("{:?}", outcome);
+}
+
+// The ruleset settings, with any given on the command line replacing the ones passed in.
+fn settings_flags(args: &[String], mut settings: Settings) -> Settings {
+    settings.food_spawn_chance =
+        config::flag(args, "food-spawn-chance", settings.food_spawn_chance);
+    settings.minimum_food = config::flag(args, "minimum-food", settings.minimum_food);
+    settings.hazard_damage_per_turn =
+        config::flag(args, "hazard-damage", settings.hazard_damage_per_turn);
+    settings.royale.shrink_every_n_turns =
+        config::flag(args, "shrink-every", settings.royale.shrink_every_n_turns);
+    settings
+}
+
+// Squares from a list like "0:0,5:10" of x:y coordinates. A square listed more than once gets its
+// hazards stacked.
+fn parse_hazards(hazards: &str, width: u8) -> Vec<u16> {
+    hazards
+        .split(',')
+        .filter(|square| !square.is_empty())
+        .map(|square| {
+            let coords: Vec<u16> = square
+                .split(':')
+                .map(|coord| coord.parse().ok())
+                .collect::<Option<Vec<u16>>>()
+                .filter(|coords| coords.len() == 2)
+                .unwrap_or_else(|| panic!("--hazards needs x:y squares, not {}", square));
+            coords[1] * width as u16 + coords[0]
+        })
+        .collect()
+}
+
+// Puts the snakes on squads of the given size in the order they were added.
+fn assign_squads(game: &mut Game, squad_size: usize) {
+    for (snake_idx, snake) in game.snakes.iter_mut().enumerate() {
+        snake.squad = (snake_idx / squad_size.max(1)) as u8;
+    }
+    game.rehash();
+}
+
+// The weights from the given flag, or the ones the deployment is configured with.
+pub fn weights_flag(args: &[String], name: &str, config: &Config) -> EvalWeights {
+    match config::flag::<String>(args, name, String::new()).as_str() {
+        "" => config.weights.clone(),
+        source => EvalWeights::load(source),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_simulate() {
+        let pool = ThreadPool::new(1);
+        // an apple by each snake and one in the center
+        let mut rng = Rng::new(7);
+        let game = new_game(11, 11, 8, &mut rng);
+        assert_eq!(game.apples.iter().filter(|apple| **apple).count(), 9);
+        assert!(game.apples[60]);
+        for snake in &game.snakes {
+            let head = snake.positions[0];
+            assert_eq!(
+                [head - 12, head - 10, head + 10, head + 12]
+                    .iter()
+                    .filter(|pos| game.apples[**pos as usize])
+                    .count(),
+                1
+            );
+        }
+
+        // a snake that just keeps going up runs into the wall, and the one looking ahead doesn't
+        let mut rng = Rng::new(7);
+        let mut game = new_game(11, 11, 2, &mut rng);
+        let mut drivers = vec![
+            Driver::Engine(EngineConfig {
+                move_time: Duration::from_secs(60),
+                max_depth: 2,
+                weights: EvalWeights::default(),
+                search: SearchMode::Auto,
+                engine: Engine::AlphaBeta,
+            }),
+            Driver::Callback(Box::new(|_, _| 0)),
+        ];
+        let outcome = play(&mut game, &mut drivers, &pool, &mut rng, 100);
+        assert_eq!(outcome.winners, vec![0]);
+        assert!(outcome.turns <= 10);
+        assert!(game.apples.iter().any(|apple| *apple));
+
+        // the same seed plays the same game
+        let first_legal = || {
+            Driver::Callback(Box::new(|game: &Game, idx| {
+                game.legal_moves(idx).first().copied().unwrap_or(0)
+            }))
+        };
+        let mut games = Vec::new();
+        for _ in 0..2 {
+            let mut rng = Rng::new(3);
+            let mut game = new_game(7, 7, 4, &mut rng);
+            let mut drivers = vec![first_legal(), first_legal(), first_legal(), first_legal()];
+            let outcome = play(&mut game, &mut drivers, &pool, &mut rng, 50);
+            games.push((outcome, game.apples, game.hash));
+        }
+        assert_eq!(games[0], games[1]);
+
+        // placed hazards stay put and wear a snake down
+        let mut game = Game::new(7, 7);
+        game.add_start_snake(3);
+        game.add_start_snake(48);
+        for pos in parse_hazards("3:1,3:1,3:2", 7) {
+            game.hazards[pos as usize] += 1;
+        }
+        assert_eq!(game.hazards[10], 2);
+        assert_eq!(game.hazards[17], 1);
+        game.settings = settings_flags(
+            &[
+                "--hazard-damage".to_string(),
+                "30".to_string(),
+                "--minimum-food".to_string(),
+                "0".to_string(),
+                "--food-spawn-chance".to_string(),
+                "0".to_string(),
+            ],
+            game.settings,
+        );
+        game.rehash();
+        let mut drivers = vec![
+            Driver::Callback(Box::new(|_, _| 0)),
+            Driver::Callback(Box::new(|game: &Game, idx| {
+                game.legal_moves(idx).first().copied().unwrap_or(0)
+            })),
+        ];
+        play(&mut game, &mut drivers, &pool, &mut rng, 2);
+        assert_eq!(game.snakes[0].health, 100 - 2 - 30 * 3);
+        assert_eq!(game.hazards[10], 2);
+
+        // squads of two, in the order the snakes were added
+        let mut game = new_game(11, 11, 4, &mut rng);
+        game.ruleset = Ruleset::Squad;
+        assign_squads(&mut game, 2);
+        assert!(game.same_squad(0, 1));
+        assert!(game.same_squad(2, 3));
+        assert!(!game.same_squad(1, 2));
+    }
+}
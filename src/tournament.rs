@@ -0,0 +1,203 @@
+use crate::config::{self, Config};
+use crate::pool::ThreadPool;
+use crate::simulate::{self, Driver, EngineConfig, Rng};
+use std::time::Duration;
+
+// Error rates of the sequential probability ratio test.
+const ALPHA: f64 = 0.05;
+const BETA: f64 = 0.05;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Verdict {
+    // The new engine is no more than elo0 better.
+    H0,
+    // The new engine is at least elo1 better.
+    H1,
+    Undecided,
+}
+
+// Game results from the point of view of the new engine.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Tally {
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+}
+
+impl Tally {
+    pub fn games(&self) -> u32 {
+        self.wins + self.losses + self.draws
+    }
+
+    // Average points per game, with a draw worth half a win.
+    pub fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games() as f64
+    }
+
+    // The Elo difference the score works out to. Clamped so a clean sweep isn't infinite.
+    pub fn elo(&self) -> f64 {
+        let score = self.score().clamp(0.001, 0.999);
+        -400.0 * (1.0 / score - 1.0).log10()
+    }
+
+    // Log likelihood ratio of the new engine being elo1 better rather than elo0 better, using the
+    // normal approximation of the per game score.
+    pub fn llr(&self, elo0: f64, elo1: f64) -> f64 {
+        if self.games() == 0 {
+            return 0.0;
+        }
+        let games = self.games() as f64;
+        let score = self.score();
+        // counted with an extra win and loss, so a run where every game went the same way still
+        // has some variance and can be decided instead of looking infinitely certain or not at all
+        let variance = ((self.wins + 1) as f64 * (1.0 - score).powi(2)
+            + (self.losses + 1) as f64 * score.powi(2)
+            + self.draws as f64 * (0.5 - score).powi(2))
+            / (games + 2.0);
+        let score0 = expected_score(elo0);
+        let score1 = expected_score(elo1);
+        games * (score1 - score0) * (2.0 * score - score0 - score1) / (2.0 * variance)
+    }
+
+    pub fn sprt(&self, elo0: f64, elo1: f64) -> Verdict {
+        let llr = self.llr(elo0, elo1);
+        if llr >= ((1.0 - BETA) / ALPHA).ln() {
+            Verdict::H1
+        } else if llr <= (BETA / (1.0 - ALPHA)).ln() {
+            Verdict::H0
+        } else {
+            Verdict::Undecided
+        }
+    }
+}
+
+fn expected_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+// Plays pairs of head to head games from the same seed, with the engines swapping starting
+// positions in the second game of each pair, until the SPRT is decided or max_games are played.
+#[allow(clippy::too_many_arguments)]
+pub fn run(
+    new: &EngineConfig,
+    old: &EngineConfig,
+    width: u8,
+    height: u8,
+    seed: u64,
+    max_games: u32,
+    max_turns: u32,
+    (elo0, elo1): (f64, f64),
+    pool: &ThreadPool,
+) -> Tally {
+    let mut tally = Tally::default();
+    for game_idx in 0..max_games {
+        let mut rng = Rng::new(seed + (game_idx / 2) as u64);
+        let mut game = simulate::new_game(width, height, 2, &mut rng);
+        let new_idx = (game_idx % 2) as usize;
+        let mut drivers = vec![Driver::Engine(new.clone()), Driver::Engine(old.clone())];
+        if new_idx == 1 {
+            drivers.reverse();
+        }
+        let outcome = simulate::play(&mut game, &mut drivers, pool, &mut rng, max_turns);
+        match outcome.winners.first() {
+            Some(winner) if *winner == new_idx => tally.wins += 1,
+            Some(_) => tally.losses += 1,
+            None => tally.draws += 1,
+        }
+        println!("game {}: {:?}, {:?}", game_idx, outcome, tally);
+        if tally.sprt(elo0, elo1) != Verdict::Undecided {
+            break;
+        }
+    }
+    tally
+}
+
+// `algae tournament`: pits a new engine configuration against an old one and prints the results.
+pub fn command(args: &[String], config: &Config, pool: &ThreadPool) {
+    let engine = |side: &str| EngineConfig {
+        move_time: Duration::from_millis(config::flag(args, &format!("{}-move-time", side), 100)),
+        max_depth: config::flag(args, &format!("{}-depth", side), u8::MAX),
+        weights: simulate::weights_flag(args, &format!("{}-weights", side), config),
+        search: config::flag(args, &format!("{}-search", side), config.search),
+        engine: config::flag(args, &format!("{}-engine", side), config.engine),
+    };
+    let new = engine("new");
+    let old = engine("old");
+    let elo0 = config::flag(args, "elo0", 0.0);
+    let elo1 = config::flag(args, "elo1", 10.0);
+    let tally = run(
+        &new,
+        &old,
+        config::flag(args, "width", 11),
+        config::flag(args, "height", 11),
+        config::flag(args, "seed", 0),
+        config::flag(args, "games", 1000),
+        config::flag(args, "max-turns", 1000),
+        (elo0, elo1),
+        pool,
+    );
+    println!(
+        "{} wins, {} losses, {} draws, Elo difference {:.1}, LLR {:.2}, SPRT [{}, {}]: {:?}",
+        tally.wins,
+        tally.losses,
+        tally.draws,
+        tally.elo(),
+        tally.llr(elo0, elo1),
+        elo0,
+        elo1,
+        tally.sprt(elo0, elo1)
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tally() {
+        let even = Tally {
+            wins: 40,
+            losses: 40,
+            draws: 20,
+        };
+        assert_eq!(even.score(), 0.5);
+        assert!(even.elo().abs() < 1e-9);
+        assert_eq!(even.sprt(0.0, 10.0), Verdict::Undecided);
+
+        let stronger = Tally {
+            wins: 300,
+            losses: 150,
+            draws: 50,
+        };
+        assert!((stronger.elo() - 107.5).abs() < 0.1);
+        assert_eq!(stronger.sprt(0.0, 10.0), Verdict::H1);
+
+        let weaker = Tally {
+            wins: 150,
+            losses: 300,
+            draws: 50,
+        };
+        assert_eq!(weaker.sprt(0.0, 10.0), Verdict::H0);
+
+        // a clean sweep either way decides it early
+        let sweep = Tally {
+            wins: 50,
+            losses: 0,
+            draws: 0,
+        };
+        assert_eq!(sweep.sprt(0.0, 10.0), Verdict::H1);
+        let swept = Tally {
+            wins: 0,
+            losses: 50,
+            draws: 0,
+        };
+        assert_eq!(swept.sprt(0.0, 10.0), Verdict::H0);
+        assert_eq!(Tally::default().sprt(0.0, 10.0), Verdict::Undecided);
+        let drawn = Tally {
+            wins: 0,
+            losses: 0,
+            draws: 1,
+        };
+        assert_eq!(drawn.sprt(0.0, 10.0), Verdict::Undecided);
+    }
+}
@@ -0,0 +1,115 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Bound {
+    Exact,
+    // The real score is at least this high.
+    Lower,
+    // The real score is at most this high.
+    Upper,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Entry {
+    pub score: f64,
+    pub depth: u8,
+    pub bound: Bound,
+    pub best_move: Option<u8>,
+}
+
+// A fixed-size hash table shared between all the search threads. Entries are stored lockless as
+// the key xored with the data next to the data itself, so an entry torn by two threads writing at
+// once just fails the key check instead of handing back garbage.
+pub struct TranspositionTable {
+    entries: Vec<(AtomicU64, AtomicU64)>,
+}
+
+impl TranspositionTable {
+    // The size is rounded up to a power of two so the hash can be masked into an index.
+    pub fn new(size: usize) -> TranspositionTable {
+        let size = size.next_power_of_two();
+        let mut entries = Vec::with_capacity(size);
+        for _ in 0..size {
+            entries.push((AtomicU64::new(0), AtomicU64::new(0)));
+        }
+        TranspositionTable { entries }
+    }
+
+    pub fn get(&self, hash: u64) -> Option<Entry> {
+        let (stored_key, stored_data) = &self.entries[hash as usize & (self.entries.len() - 1)];
+        let data = stored_data.load(Ordering::Relaxed);
+        if data == 0 || stored_key.load(Ordering::Relaxed) ^ data != hash {
+            return None;
+        }
+        Some(unpack(data))
+    }
+
+    // Always replaces, since newer entries come from deeper iterations more often than not.
+    pub fn insert(&self, hash: u64, entry: Entry) {
+        let (stored_key, stored_data) = &self.entries[hash as usize & (self.entries.len() - 1)];
+        let data = pack(entry);
+        stored_key.store(hash ^ data, Ordering::Relaxed);
+        stored_data.store(data, Ordering::Relaxed);
+    }
+}
+
+// Layout from the low bits up: 32 bits of f32 score, 8 bits of depth, 2 bits of bound, 3 bits of
+// best move (4 for none), and a set bit so no real entry is ever 0.
+fn pack(entry: Entry) -> u64 {
+    let bound = match entry.bound {
+        Bound::Exact => 0,
+        Bound::Lower => 1,
+        Bound::Upper => 2,
+    };
+    (entry.score as f32).to_bits() as u64
+        | (entry.depth as u64) << 32
+        | bound << 40
+        | (entry.best_move.unwrap_or(4) as u64) << 42
+        | 1 << 45
+}
+
+fn unpack(data: u64) -> Entry {
+    let bound = match (data >> 40) & 0b11 {
+        0 => Bound::Exact,
+        1 => Bound::Lower,
+        _ => Bound::Upper,
+    };
+    let best_move = ((data >> 42) & 0b111) as u8;
+    Entry {
+        score: f32::from_bits(data as u32) as f64,
+        depth: (data >> 32) as u8,
+        bound,
+        best_move: if best_move < 4 { Some(best_move) } else { None },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_table() {
+        let table = TranspositionTable::new(1000);
+        let entry = Entry {
+            score: -10000.0,
+            depth: 7,
+            bound: Bound::Upper,
+            best_move: Some(3),
+        };
+        assert_eq!(table.get(12345), None);
+        table.insert(12345, entry);
+        assert_eq!(table.get(12345), Some(entry));
+        // same slot, different position
+        assert_eq!(table.get(12345 + 1024), None);
+
+        let entry = Entry {
+            score: 1.5,
+            depth: 0,
+            bound: Bound::Exact,
+            best_move: None,
+        };
+        table.insert(12345 + 1024, entry);
+        assert_eq!(table.get(12345 + 1024), Some(entry));
+        assert_eq!(table.get(12345), None);
+    }
+}